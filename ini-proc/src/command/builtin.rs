@@ -0,0 +1,76 @@
+//! 内置命令
+//!
+//! Handlers shipped with ini-proc, registered by `command_init`.
+
+use super::clear_tf::clear_tf_cmd;
+use super::config_28181::config_28181_cmd;
+use super::coordinate::coordinate_cmd;
+use super::deep_sleep::DeepSleepHandler;
+use super::payload::*;
+use super::registry::*;
+use super::remote_capture::RemoteCaptureHandler;
+use super::set_ip::SetIpHandler;
+use super::set_sock::set_sock_cmd;
+use super::time::SetTimeHandler;
+use super::version::version_cmd;
+use super::video_tape::{VideoTapeHandler, video_tape_event_clips};
+use crate::communication::types::*;
+use crate::event::trigger::{EventAction, event_register_action};
+use crate::media::capture::{CaptureBackend, CommandCapture, capture_event_snapshots};
+use crate::media::clip::{ClipBackend, CommandClip};
+use crate::media::list::media_ipc_notify;
+use crate::ota::firmware::ota_status;
+use crate::system::clock::SystemClock;
+use crate::system::network::CommandNetwork;
+use crate::system::power::SysfsPower;
+use std::sync::Arc;
+
+/// OTA 进度查询
+async fn ota_cmd(ctx: CmdContext, _cmd: CmdPackage) {
+    let (ota_status, progress) = ota_status();
+    let resp = OtaResp {
+        status: CmdStatus::Ok,
+        ota_status: ota_status.into(),
+        progress,
+        slot: String::new(),
+    };
+    ctx.reply(&resp).await;
+}
+
+/// `emmc_monitor` is the running eMMC check thread, handed to DeepSleep.
+pub fn command_init(emmc_monitor: std::thread::JoinHandle<()>) -> Option<i32> {
+    let capture: Arc<dyn CaptureBackend> = Arc::new(CommandCapture);
+    cmd_register(CmdType::RemoteCapture, Arc::new(RemoteCaptureHandler::new(capture.clone())))?;
+    event_register_action(
+        EventAction::Capture,
+        Box::new(move |_| {
+            let capture = capture.clone();
+            tokio::spawn(async move {
+                let paths = tokio::task::spawn_blocking(move || capture_event_snapshots(capture.as_ref())).await;
+                media_ipc_notify(&paths.unwrap_or_default()).await;
+            });
+        }),
+    );
+    let clip: Arc<dyn ClipBackend> = Arc::new(CommandClip);
+    cmd_register(CmdType::VideoTape, Arc::new(VideoTapeHandler::new(clip.clone())))?;
+    event_register_action(
+        EventAction::Record,
+        Box::new(move |_| {
+            let clip = clip.clone();
+            tokio::spawn(async move {
+                let paths = tokio::task::spawn_blocking(move || video_tape_event_clips(clip.as_ref())).await;
+                media_ipc_notify(&paths.unwrap_or_default()).await;
+            });
+        }),
+    );
+    cmd_register(CmdType::Ota, Arc::new(ota_cmd))?;
+    cmd_register(CmdType::Version, Arc::new(version_cmd))?;
+    cmd_register(CmdType::SetTime, Arc::new(SetTimeHandler::new(Arc::new(SystemClock))))?;
+    cmd_register(CmdType::SetSockIpPort, Arc::new(set_sock_cmd))?;
+    cmd_register(CmdType::ClearTfCardFiles, Arc::new(clear_tf_cmd))?;
+    cmd_register(CmdType::Config28181, Arc::new(config_28181_cmd))?;
+    cmd_register(CmdType::DeepSleep, Arc::new(DeepSleepHandler::new(Arc::new(SysfsPower), emmc_monitor)))?;
+    cmd_register(CmdType::SetCoordinate, Arc::new(coordinate_cmd))?;
+    cmd_register(CmdType::SetIp, Arc::new(SetIpHandler::new(Arc::new(CommandNetwork))))?;
+    Some(0)
+}
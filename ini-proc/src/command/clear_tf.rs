@@ -0,0 +1,105 @@
+//! 清理存储卡文件
+//!
+//! ClearTfCardFiles runs `emmc_clear` on a blocking thread. While it works
+//! the latest progress is reported as `ClearTfResp` progress frames; the
+//! final response is Ok when every file was removed, Partial when some
+//! could not be, and Failed when none were. Removed and failed counts are
+//! always included. A request while a clear is running is answered Busy.
+
+use super::payload::*;
+use super::registry::*;
+use crate::communication::types::*;
+use crate::storage::emmc::{EmmcClearBusy, EmmcClearProgress, emmc_clear, emmc_get_remove_status};
+use anyhow::Result;
+use tokio::sync::watch;
+
+fn clear_tf_resp(status: CmdStatus, state: &EmmcClearProgress) -> ClearTfResp {
+    ClearTfResp {
+        status,
+        progress: state.percent(),
+        files: state.removed,
+        failed: state.failed,
+    }
+}
+
+/// 最终应答; `last` 是出错前最后一次进度
+fn clear_tf_final(mode: u8, result: Result<EmmcClearProgress>, last: &EmmcClearProgress) -> ClearTfResp {
+    match result {
+        Ok(state) if state.failed == 0 => clear_tf_resp(CmdStatus::Ok, &state),
+        Ok(state) => {
+            println!("clear mode {}: {} of {} files failed", mode, state.failed, state.total);
+            let status = if state.removed > 0 { CmdStatus::Partial } else { CmdStatus::Failed };
+            clear_tf_resp(status, &state)
+        }
+        Err(err) if err.is::<EmmcClearBusy>() => clear_tf_resp(CmdStatus::Busy, &Default::default()),
+        Err(err) => {
+            println!("clear mode {} failed: {}", mode, err);
+            clear_tf_resp(CmdStatus::Failed, last)
+        }
+    }
+}
+
+pub(crate) async fn clear_tf_cmd(ctx: CmdContext, cmd: CmdPackage) {
+    let req = match ClearTfReq::decode(&cmd.data) {
+        Ok(req) => req,
+        Err(err) => {
+            ctx.reply(&clear_tf_resp(err.into(), &Default::default())).await;
+            return;
+        }
+    };
+    // 未挂载或只读时不起清理线程
+    if !ctx.storage().is_some_and(|s| s.mount_status() && !s.is_read_only()) {
+        println!("clear mode {} refused: storage not writable", req.mode);
+        ctx.reply(&clear_tf_resp(CmdStatus::Failed, &Default::default())).await;
+        return;
+    }
+    if emmc_get_remove_status().unwrap_or(false) {
+        ctx.reply(&clear_tf_resp(CmdStatus::Busy, &Default::default())).await;
+        return;
+    }
+    let (progress_tx, mut progress_rx) = watch::channel(EmmcClearProgress::default());
+    let mut task = tokio::task::spawn_blocking(move || {
+        emmc_clear(req.mode as i32, |state| {
+            progress_tx.send_replace(*state);
+        })
+    });
+    // 只转发最新进度，删除很快时自然合并
+    let result = loop {
+        tokio::select! {
+            result = &mut task => break result,
+            Ok(()) = progress_rx.changed() => {
+                let state = *progress_rx.borrow_and_update();
+                ctx.progress(&clear_tf_resp(CmdStatus::Ok, &state)).await;
+            }
+        }
+    };
+    let last = *progress_rx.borrow();
+    let resp = match result {
+        Ok(result) => clear_tf_final(req.mode, result, &last),
+        Err(_) => clear_tf_resp(CmdStatus::Failed, &last),
+    };
+    ctx.reply(&resp).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn final_status_tells_partial_and_busy_apart() {
+        let state = |removed, failed| EmmcClearProgress {
+            total: removed + failed,
+            removed,
+            failed,
+        };
+        let last = state(1, 0);
+        assert_eq!(clear_tf_final(1, Ok(state(5, 0)), &last).status, CmdStatus::Ok);
+        let resp = clear_tf_final(1, Ok(state(3, 2)), &last);
+        assert_eq!((resp.status, resp.files, resp.failed), (CmdStatus::Partial, 3, 2));
+        assert_eq!(clear_tf_final(1, Ok(state(0, 2)), &last).status, CmdStatus::Failed);
+        assert_eq!(clear_tf_final(1, Err(EmmcClearBusy.into()), &last).status, CmdStatus::Busy);
+        let resp = clear_tf_final(1, Err(anyhow!("emmc not mounted")), &last);
+        assert_eq!((resp.status, resp.files), (CmdStatus::Failed, 1));
+    }
+}
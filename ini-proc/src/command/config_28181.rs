@@ -0,0 +1,100 @@
+//! GB28181 参数配置
+//!
+//! Config28181 carries the `gb28181` keys to change; an empty list only
+//! queries. The reply always holds the effective section, with the
+//! password masked.
+
+use super::payload::*;
+use super::registry::*;
+use crate::communication::types::*;
+use crate::config::validate::ini_mask_secret;
+use crate::system::gb28181::{GB28181_SECTION, gb28181_config, gb28181_configure};
+
+fn config_28181_masked(items: Vec<(String, String)>) -> Config28181Items {
+    let items = items
+        .into_iter()
+        .map(|(k, v)| {
+            let v = ini_mask_secret(Some(GB28181_SECTION), &k, &v).to_string();
+            (k, v)
+        })
+        .collect();
+    Config28181Items { items }
+}
+
+pub(crate) fn config_28181_apply(req: &Config28181Items) -> Config28181Resp {
+    let (status, items) = match gb28181_configure(&req.items) {
+        Ok(items) => (CmdStatus::Ok, items),
+        Err(err) => {
+            println!("config 28181 failed: {:?}", err);
+            (err.into(), gb28181_config())
+        }
+    };
+    Config28181Resp {
+        status,
+        config: config_28181_masked(items),
+    }
+}
+
+pub(crate) async fn config_28181_cmd(ctx: CmdContext, cmd: CmdPackage) {
+    let resp = match Config28181Items::decode(&cmd.data) {
+        Ok(req) => tokio::task::spawn_blocking(move || config_28181_apply(&req))
+            .await
+            .unwrap_or_else(|_| Config28181Resp {
+                status: CmdStatus::Failed,
+                config: config_28181_masked(gb28181_config()),
+            }),
+        Err(err) => Config28181Resp {
+            status: err.into(),
+            config: config_28181_masked(gb28181_config()),
+        },
+    };
+    ctx.reply(&resp).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ini_parse::{ini_get_ini_config, ini_test_init};
+
+    fn req(items: &[(&str, &str)]) -> Config28181Items {
+        Config28181Items {
+            items: items.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        }
+    }
+
+    fn value<'a>(resp: &'a Config28181Resp, key: &str) -> Option<&'a str> {
+        resp.config.items.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn partial_set_is_all_or_nothing() {
+        ini_test_init();
+
+        let resp = config_28181_apply(&req(&[("serverIp", "10.2.3.4"), ("heartBeat", "30")]));
+        assert_eq!(resp.status, CmdStatus::Ok);
+        assert_eq!(value(&resp, "serverIp"), Some("10.2.3.4"));
+        assert_eq!(value(&resp, "regTimeOut"), Some("900"));
+        assert_eq!(ini_get_ini_config("gb28181", "heartBeat").as_deref(), Some("30"));
+        assert_eq!(value(&resp, "passWord"), Some("******"));
+
+        // 一项非法则整体不生效
+        let resp = config_28181_apply(&req(&[("heartBeat", "45"), ("deviceId", "123")]));
+        assert_eq!(resp.status, CmdStatus::InvalidParam);
+        assert_eq!(value(&resp, "heartBeat"), Some("30"));
+        assert_eq!(ini_get_ini_config("gb28181", "heartBeat").as_deref(), Some("30"));
+
+        let resp = config_28181_apply(&req(&[]));
+        assert_eq!(resp.status, CmdStatus::Ok);
+        assert_eq!(value(&resp, "serverIp"), Some("10.2.3.4"));
+        assert_eq!(config_28181_apply(&req(&[("nope", "1")])).status, CmdStatus::InvalidParam);
+
+        // 查询结果原样写回时密码保持不变
+        let password = ini_get_ini_config("gb28181", "passWord");
+        let resp = config_28181_apply(&req(&[]));
+        let resp = config_28181_apply(&resp.config);
+        assert_eq!(resp.status, CmdStatus::Ok);
+        assert_eq!(value(&resp, "passWord"), Some("******"));
+        assert_eq!(ini_get_ini_config("gb28181", "passWord"), password);
+        assert_ne!(password.as_deref(), Some("******"));
+    }
+}
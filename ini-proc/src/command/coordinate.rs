@@ -0,0 +1,66 @@
+//! OSD 坐标设置
+//!
+//! SetCoordinate stores the OSD corner, or with `query` set only reports
+//! it. Either way the reply carries the value now in effect.
+
+use super::payload::*;
+use super::registry::*;
+use crate::communication::types::*;
+use crate::system::osd::{osd_coordinate_get, osd_coordinate_set};
+
+pub(crate) fn coordinate_apply(req: &SetCoordinateReq) -> CoordinateResp {
+    let status = if req.query {
+        CmdStatus::Ok
+    } else {
+        match osd_coordinate_set(req.direction) {
+            Ok(()) => CmdStatus::Ok,
+            Err(err) => {
+                println!("set coordinate {:?} failed: {:?}", req.direction, err);
+                CmdStatus::Failed
+            }
+        }
+    };
+    CoordinateResp {
+        status,
+        direction: osd_coordinate_get(),
+    }
+}
+
+pub(crate) async fn coordinate_cmd(ctx: CmdContext, cmd: CmdPackage) {
+    let resp = match SetCoordinateReq::decode(&cmd.data) {
+        Ok(req) => tokio::task::spawn_blocking(move || coordinate_apply(&req))
+            .await
+            .unwrap_or(CoordinateResp {
+                status: CmdStatus::Failed,
+                direction: osd_coordinate_get(),
+            }),
+        Err(err) => CoordinateResp {
+            status: err.into(),
+            direction: osd_coordinate_get(),
+        },
+    };
+    ctx.reply(&resp).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ini_parse::{ini_get_ini_config, ini_test_init};
+
+    #[test]
+    fn set_persists_and_query_reports_current() {
+        ini_test_init();
+        let set = |query, direction| coordinate_apply(&SetCoordinateReq { query, direction });
+
+        let resp = set(false, Direction::BottomRight);
+        assert_eq!(resp.status, CmdStatus::Ok);
+        assert_eq!(resp.direction, Direction::BottomRight);
+        assert_eq!(ini_get_ini_config("system", "coordinate").as_deref(), Some("9"));
+
+        // 查询时忽略请求里的方向
+        let resp = set(true, Direction::Top);
+        assert_eq!(resp.direction, Direction::BottomRight);
+
+        assert_eq!(set(false, Direction::CoordinateAll).direction, Direction::CoordinateAll);
+    }
+}
@@ -0,0 +1,216 @@
+//! 深度休眠
+//!
+//! DeepSleep quiesces the power stages (recording and media writers, file
+//! flush, eMMC monitor), answers DeepSleepResp, closes the client
+//! sessions and only then suspends. After wake-up the stages are restored in reverse order
+//! and the MCU reconnects. If a stage cannot be quiesced the device stays
+//! up and the command fails.
+
+use super::payload::*;
+use super::registry::*;
+use crate::communication::tcp_transport::tcp_server_close_sessions;
+use crate::communication::types::*;
+use crate::system::power::*;
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+/// 等待应答发出、连接关闭后再休眠
+const SESSION_CLOSE_GRACE: Duration = Duration::from_millis(300);
+static SLEEPING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone)]
+pub struct DeepSleepHandler {
+    power: Arc<dyn PowerBackend>,
+    stages: Vec<Arc<dyn PowerStage>>,
+    close_grace: Duration,
+}
+
+/// 休眠顺序: 先停写入，再落盘，最后停 eMMC 检测
+fn deep_sleep_stages(recorder: RecorderStage, monitor: StorageMonitorStage) -> Vec<Arc<dyn PowerStage>> {
+    vec![Arc::new(recorder), Arc::new(FlushStage), Arc::new(monitor)]
+}
+
+impl DeepSleepHandler {
+    /// `monitor` is the running eMMC check thread, stopped during sleep.
+    pub fn new(power: Arc<dyn PowerBackend>, monitor: thread::JoinHandle<()>) -> Self {
+        Self {
+            power,
+            stages: deep_sleep_stages(RecorderStage::default(), StorageMonitorStage::new(monitor)),
+            close_grace: SESSION_CLOSE_GRACE,
+        }
+    }
+
+    async fn run(&self, ctx: &CmdContext, req: DeepSleepReq) -> Option<CmdStatus> {
+        let stages = self.stages.clone();
+        let quiesced = tokio::task::spawn_blocking(move || power_quiesce(&stages)).await;
+        match quiesced {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => {
+                println!("deep sleep aborted: {:?}", err);
+                return Some(CmdStatus::Failed);
+            }
+            Err(_) => return Some(CmdStatus::Failed),
+        }
+        ctx.respond_status(CmdStatus::Ok).await;
+        if tcp_server_close_sessions().await.is_none() {
+            println!("tcp server not running, no session to close");
+        }
+        tokio::time::sleep(self.close_grace).await;
+
+        println!("deep sleep for {}s", req.seconds);
+        let power = self.power.clone();
+        let stages = self.stages.clone();
+        let _ = tokio::task::spawn_blocking(move || {
+            if let Err(err) = power.suspend(req.seconds) {
+                println!("suspend failed: {:?}", err);
+            }
+            power_restore(&stages);
+        })
+        .await;
+        println!("woke up from deep sleep");
+        None
+    }
+}
+
+impl CmdHandler for DeepSleepHandler {
+    fn handle(&self, ctx: CmdContext, cmd: CmdPackage) -> CmdFuture {
+        let this = self.clone();
+        Box::pin(async move {
+            let req = match DeepSleepReq::decode(&cmd.data) {
+                Ok(req) => req,
+                Err(err) => {
+                    ctx.respond_status(err.into()).await;
+                    return;
+                }
+            };
+            if SLEEPING.swap(true, Ordering::SeqCst) {
+                ctx.respond_status(CmdStatus::Busy).await;
+                return;
+            }
+            // 休眠成功时应答已在关闭连接前发出
+            if let Some(status) = this.run(&ctx, req).await {
+                ctx.respond_status(status).await;
+            }
+            SLEEPING.store(false, Ordering::SeqCst);
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communication::protocol::*;
+    use anyhow::{Result, bail};
+    use std::sync::Mutex;
+    use tokio::sync::mpsc;
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    struct MockPower(Log);
+
+    impl PowerBackend for MockPower {
+        fn suspend(&self, seconds: u32) -> Result<()> {
+            self.0.lock().unwrap().push(format!("suspend {}", seconds));
+            Ok(())
+        }
+    }
+
+    struct MockRecorder {
+        log: Log,
+        broken: bool,
+    }
+
+    impl RecorderControl for MockRecorder {
+        fn pause(&self, _timeout: Duration) -> Result<()> {
+            if self.broken {
+                bail!("recorder busy");
+            }
+            self.log.lock().unwrap().push("pause recorder".to_string());
+            Ok(())
+        }
+        fn resume(&self) -> Result<()> {
+            self.log.lock().unwrap().push("resume recorder".to_string());
+            Ok(())
+        }
+    }
+
+    /// 真实的休眠阶段，录像进程和检测线程用 mock
+    fn handler(log: &Log, broken: bool) -> DeepSleepHandler {
+        let recorder = RecorderStage::new(
+            Box::leak(Box::new(MediaWriters::new())),
+            Arc::new(MockRecorder {
+                log: log.clone(),
+                broken,
+            }),
+            Duration::from_millis(50),
+        );
+        let (start_log, stop_log) = (log.clone(), log.clone());
+        let monitor = StorageMonitorStage::with(
+            thread::spawn(|| ()),
+            Box::new(move || {
+                start_log.lock().unwrap().push("start monitor".to_string());
+                thread::spawn(|| ())
+            }),
+            Box::new(move |handle| {
+                handle.join().unwrap();
+                stop_log.lock().unwrap().push("stop monitor".to_string());
+            }),
+        );
+        DeepSleepHandler {
+            power: Arc::new(MockPower(log.clone())),
+            stages: deep_sleep_stages(recorder, monitor),
+            close_grace: Duration::ZERO,
+        }
+    }
+
+    async fn deep_sleep(handler: DeepSleepHandler) -> CmdStatus {
+        let (tx, mut rx) = mpsc::channel(8);
+        let ctx = CmdContext {
+            req: McuComPackageHead {
+                pack_head_flg: PACKAGE_HEAD_FLAG,
+                data_len: 0,
+                crc: 0,
+                mcu_id: 2,
+                sn: 70,
+                src_sn: 0,
+                msg_type: McuComMsgType::Cmd as u16,
+            },
+            cmd_type: CmdType::DeepSleep,
+            tx,
+        };
+        let cmd = CmdPackage::new(CmdType::DeepSleep as u16, &DeepSleepReq { seconds: 60 }.encode());
+        handler.handle(ctx, cmd).await;
+        let ParseResult::Success(pack) = parse_package_head(&rx.recv().await.unwrap()) else {
+            panic!("bad frame");
+        };
+        assert!(rx.try_recv().is_err());
+        StatusResp::decode(&pack.as_cmd().unwrap().data).unwrap().status
+    }
+
+    // 两种情况共用 SLEEPING，放在一个测试里顺序执行
+    #[tokio::test]
+    async fn stages_quiesce_in_order_and_restore_in_reverse() {
+        let log = Log::default();
+        assert_eq!(deep_sleep(handler(&log, false)).await, CmdStatus::Ok);
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "pause recorder",
+                "stop monitor",
+                "suspend 60",
+                "start monitor",
+                "resume recorder",
+            ]
+        );
+
+        let log = Log::default();
+        assert_eq!(deep_sleep(handler(&log, true)).await, CmdStatus::Failed);
+        assert!(log.lock().unwrap().is_empty());
+    }
+}
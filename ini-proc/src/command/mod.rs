@@ -0,0 +1,13 @@
+pub mod builtin;
+pub mod clear_tf;
+pub mod config_28181;
+pub mod coordinate;
+pub mod deep_sleep;
+pub mod payload;
+pub mod registry;
+pub mod remote_capture;
+pub mod set_ip;
+pub mod set_sock;
+pub mod time;
+pub mod version;
+pub mod video_tape;
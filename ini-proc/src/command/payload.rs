@@ -0,0 +1,886 @@
+//! 命令报文体
+//!
+//! Typed request and response bodies for every `CmdType`. All fields are
+//! little-endian, strings are `str8` (`| len u8 | utf8 ... |`) and IPv4
+//! addresses are four octets in network order. Every response starts with
+//! a `CmdStatus` byte. Parsing is strict: short data, trailing bytes and
+//! out-of-range values are rejected.
+
+use crate::communication::codec::{WireReader, WireWrite};
+use crate::communication::types::*;
+use crate::config::validate::ConfigError;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use std::net::Ipv4Addr;
+
+pub const CAR_CODE_MAX: usize = 32;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive)]
+pub enum CmdStatus {
+    Ok = 0,
+    Failed = 1,
+    Unsupported = 2,
+    InvalidParam = 3,
+    Busy = 4,
+    BadLength = 5,
+    BadValue = 6,
+    /// 部分完成，应答里带有失败数
+    Partial = 7,
+    /// 已受理，只用于受理应答，结果随后由 *Resp 给出
+    Accepted = 8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PayloadError {
+    TooShort,
+    TrailingData,
+    BadValue(&'static str),
+}
+
+impl From<PayloadError> for CmdStatus {
+    fn from(err: PayloadError) -> Self {
+        match err {
+            PayloadError::TooShort | PayloadError::TrailingData => CmdStatus::BadLength,
+            PayloadError::BadValue(_) => CmdStatus::BadValue,
+        }
+    }
+}
+
+impl From<ConfigError> for CmdStatus {
+    fn from(err: ConfigError) -> Self {
+        match err {
+            ConfigError::NotFound | ConfigError::ReadOnly | ConfigError::InvalidValue => CmdStatus::InvalidParam,
+            ConfigError::NotInitialized | ConfigError::PersistFailed => CmdStatus::Failed,
+        }
+    }
+}
+
+pub trait CmdPayload: Sized {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError>;
+    fn write(&self, out: &mut Vec<u8>);
+
+    fn decode(data: &[u8]) -> Result<Self, PayloadError> {
+        let mut r = Reader(WireReader::new(data));
+        let v = Self::read(&mut r)?;
+        if r.0.remaining() != 0 {
+            return Err(PayloadError::TrailingData);
+        }
+        Ok(v)
+    }
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write(&mut out);
+        out
+    }
+}
+
+/// `WireReader` with payload errors and the composite field types.
+pub struct Reader<'a>(WireReader<'a>);
+
+impl Reader<'_> {
+    pub fn u8(&mut self) -> Result<u8, PayloadError> {
+        self.0.u8().map_err(|_| PayloadError::TooShort)
+    }
+    pub fn u16(&mut self) -> Result<u16, PayloadError> {
+        self.0.u16().map_err(|_| PayloadError::TooShort)
+    }
+    pub fn u32(&mut self) -> Result<u32, PayloadError> {
+        self.0.u32().map_err(|_| PayloadError::TooShort)
+    }
+    pub fn bool(&mut self, field: &'static str) -> Result<bool, PayloadError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(PayloadError::BadValue(field)),
+        }
+    }
+    pub fn str8(&mut self, field: &'static str) -> Result<String, PayloadError> {
+        let len = self.u8()? as usize;
+        let bytes = self.0.bytes(len).map_err(|_| PayloadError::TooShort)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| PayloadError::BadValue(field))
+    }
+    pub fn ipv4(&mut self) -> Result<Ipv4Addr, PayloadError> {
+        let b = self.0.bytes(4).map_err(|_| PayloadError::TooShort)?;
+        Ok(Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+    }
+    pub fn status(&mut self) -> Result<CmdStatus, PayloadError> {
+        CmdStatus::try_from(self.u8()?).map_err(|_| PayloadError::BadValue("status"))
+    }
+    pub fn rest(&mut self) -> Vec<u8> {
+        self.0.rest().to_vec()
+    }
+}
+
+fn put_str8(out: &mut Vec<u8>, s: &str) {
+    // 超长字符串在 UTF-8 边界处截断
+    let mut len = s.len().min(u8::MAX as usize);
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    out.put_u8(len as u8);
+    out.extend_from_slice(&s.as_bytes()[..len]);
+}
+
+fn put_ipv4(out: &mut Vec<u8>, ip: Ipv4Addr) {
+    out.extend_from_slice(&ip.octets());
+}
+
+// ---- 请求 ----
+
+/// Ota, Version
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmptyReq;
+
+impl CmdPayload for EmptyReq {
+    fn read(_: &mut Reader) -> Result<Self, PayloadError> {
+        Ok(EmptyReq)
+    }
+    fn write(&self, _: &mut Vec<u8>) {}
+}
+
+/// Reserved1/2 and the HeartBeat command carry opaque bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawReq {
+    pub data: Vec<u8>,
+}
+
+impl CmdPayload for RawReq {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        Ok(Self { data: r.rest() })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.data);
+    }
+}
+
+/// RemoteCapture, CloseRtmpMode, VideoOn, VideoOff: `| channel u8 |`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelReq {
+    pub channel: u8,
+}
+
+impl CmdPayload for ChannelReq {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        Ok(Self { channel: r.u8()? })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u8(self.channel);
+    }
+}
+
+/// `| channel u8 | pre_s u16 | duration_s u16 |`
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoTapeReq {
+    pub channel: u8,
+    pub pre_s: u16,
+    pub duration_s: u16,
+}
+
+impl CmdPayload for VideoTapeReq {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        let v = Self {
+            channel: r.u8()?,
+            pre_s: r.u16()?,
+            duration_s: r.u16()?,
+        };
+        if v.duration_s == 0 {
+            return Err(PayloadError::BadValue("duration_s"));
+        }
+        Ok(v)
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u8(self.channel);
+        out.put_u16(self.pre_s);
+        out.put_u16(self.duration_s);
+    }
+}
+
+/// `| name str8 |` 需要补传的文件名
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetransmitReq {
+    pub name: String,
+}
+
+impl CmdPayload for RetransmitReq {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        let name = r.str8("name")?;
+        if name.is_empty() || name.contains(['/', '\\']) {
+            return Err(PayloadError::BadValue("name"));
+        }
+        Ok(Self { name })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        put_str8(out, &self.name);
+    }
+}
+
+/// `| channel u8 | url str8 |`, an empty url uses `network/rtmp`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RtmpOpenReq {
+    pub channel: u8,
+    pub url: String,
+}
+
+impl CmdPayload for RtmpOpenReq {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        let channel = r.u8()?;
+        let url = r.str8("url")?;
+        if !url.is_empty() && !url.starts_with("rtmp://") {
+            return Err(PayloadError::BadValue("url"));
+        }
+        Ok(Self { channel, url })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u8(self.channel);
+        put_str8(out, &self.url);
+    }
+}
+
+/// Config28181 request and response body: `| count u8 | (key str8, value str8) ... |`
+/// with keys of the INI `gb28181` section. A request only carries the
+/// keys to change.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Config28181Items {
+    pub items: Vec<(String, String)>,
+}
+
+impl CmdPayload for Config28181Items {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        let count = r.u8()?;
+        let mut items = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let key = r.str8("key")?;
+            if key.is_empty() {
+                return Err(PayloadError::BadValue("key"));
+            }
+            items.push((key, r.str8("value")?));
+        }
+        Ok(Self { items })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u8(self.items.len().min(u8::MAX as usize) as u8);
+        for (key, value) in self.items.iter().take(u8::MAX as usize) {
+            put_str8(out, key);
+            put_str8(out, value);
+        }
+    }
+}
+
+/// `| seconds u32 |`, 0 sleeps until woken by the MCU
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeepSleepReq {
+    pub seconds: u32,
+}
+
+impl CmdPayload for DeepSleepReq {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        Ok(Self { seconds: r.u32()? })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u32(self.seconds);
+    }
+}
+
+/// `| mode u8 |`: 0 录像, 1 照片, 2 短视频, 3 全部
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClearTfReq {
+    pub mode: u8,
+}
+
+impl CmdPayload for ClearTfReq {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        let mode = r.u8()?;
+        if mode > 3 {
+            return Err(PayloadError::BadValue("mode"));
+        }
+        Ok(Self { mode })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u8(self.mode);
+    }
+}
+
+/// `| time_s u32 | time_zone i8 | write_rtc u8 |`, same units as the heartbeat
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetTimeReq {
+    pub time_s: u32,
+    pub time_zone: i8,
+    pub write_rtc: bool,
+}
+
+impl CmdPayload for SetTimeReq {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        let time_s = r.u32()?;
+        let time_zone = r.u8()? as i8;
+        if !(-12..=14).contains(&time_zone) {
+            return Err(PayloadError::BadValue("time_zone"));
+        }
+        Ok(Self {
+            time_s,
+            time_zone,
+            write_rtc: r.bool("write_rtc")?,
+        })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u32(self.time_s);
+        out.put_u8(self.time_zone as u8);
+        out.put_u8(self.write_rtc.into());
+    }
+}
+
+/// `| channel u8 | mirror u8 | flip u8 |`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetFlipReq {
+    pub channel: u8,
+    pub mirror: bool,
+    pub flip: bool,
+}
+
+impl CmdPayload for SetFlipReq {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        Ok(Self {
+            channel: r.u8()?,
+            mirror: r.bool("mirror")?,
+            flip: r.bool("flip")?,
+        })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u8(self.channel);
+        out.put_u8(self.mirror.into());
+        out.put_u8(self.flip.into());
+    }
+}
+
+/// `| code str8 |` OSD 车牌号, 1..=CAR_CODE_MAX bytes
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetCarCodeReq {
+    pub code: String,
+}
+
+impl CmdPayload for SetCarCodeReq {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        let code = r.str8("code")?;
+        if code.is_empty() || code.len() > CAR_CODE_MAX || code.chars().any(char::is_control) {
+            return Err(PayloadError::BadValue("code"));
+        }
+        Ok(Self { code })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        put_str8(out, &self.code);
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive)]
+pub enum LinkType {
+    Eth = 0,
+    Cellular = 1,
+}
+
+/// `| link_type u8 | dhcp u8 | ip [4] | netmask [4] | gateway [4] |`
+/// Addresses are ignored (all zero) with dhcp.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetIpReq {
+    pub link_type: LinkType,
+    pub dhcp: bool,
+    pub ip: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+}
+
+impl CmdPayload for SetIpReq {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        let link_type = LinkType::try_from(r.u8()?).map_err(|_| PayloadError::BadValue("link_type"))?;
+        let v = Self {
+            link_type,
+            dhcp: r.bool("dhcp")?,
+            ip: r.ipv4()?,
+            netmask: r.ipv4()?,
+            gateway: r.ipv4()?,
+        };
+        if !v.dhcp {
+            if v.ip.is_unspecified() || v.ip.is_broadcast() || v.ip.is_multicast() {
+                return Err(PayloadError::BadValue("ip"));
+            }
+            // 掩码必须是连续的 1
+            let mask = u32::from(v.netmask);
+            if mask == 0 || mask.leading_ones() + mask.trailing_zeros() != 32 {
+                return Err(PayloadError::BadValue("netmask"));
+            }
+            // 网关必须在新地址的网段内
+            let gateway = u32::from(v.gateway);
+            if gateway & mask != u32::from(v.ip) & mask || v.gateway == v.ip {
+                return Err(PayloadError::BadValue("gateway"));
+            }
+        }
+        Ok(v)
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u8(self.link_type.into());
+        out.put_u8(self.dhcp.into());
+        put_ipv4(out, self.ip);
+        put_ipv4(out, self.netmask);
+        put_ipv4(out, self.gateway);
+    }
+}
+
+/// SetSockIpPort request and response address: `| ip [4] | port u16 |`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SockAddrReq {
+    pub ip: Ipv4Addr,
+    pub port: u16,
+}
+
+impl CmdPayload for SockAddrReq {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        let v = Self {
+            ip: r.ipv4()?,
+            port: r.u16()?,
+        };
+        if v.port == 0 {
+            return Err(PayloadError::BadValue("port"));
+        }
+        if v.ip.is_broadcast() || v.ip.is_multicast() {
+            return Err(PayloadError::BadValue("ip"));
+        }
+        Ok(v)
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        put_ipv4(out, self.ip);
+        out.put_u16(self.port);
+    }
+}
+
+/// `| query u8 | direction u8 |`; direction is ignored for a query
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetCoordinateReq {
+    pub query: bool,
+    pub direction: Direction,
+}
+
+impl CmdPayload for SetCoordinateReq {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        let query = r.bool("query")?;
+        let direction = r.u8()?;
+        let direction = match Direction::try_from(direction) {
+            Ok(d) if d != Direction::EXTRA => d,
+            _ if query => Direction::Top,
+            _ => return Err(PayloadError::BadValue("direction")),
+        };
+        Ok(Self { query, direction })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u8(self.query.into());
+        out.put_u8(self.direction.into());
+    }
+}
+
+// ---- 应答 ----
+
+/// Responses that only carry the result.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusResp {
+    pub status: CmdStatus,
+}
+
+impl CmdPayload for StatusResp {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        Ok(Self { status: r.status()? })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u8(self.status.into());
+    }
+}
+
+/// RemoteCaptureResp, VideoTapeResp: `| status | file str8 |`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileResp {
+    pub status: CmdStatus,
+    pub file: String,
+}
+
+impl CmdPayload for FileResp {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        Ok(Self {
+            status: r.status()?,
+            file: r.str8("file")?,
+        })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u8(self.status.into());
+        put_str8(out, &self.file);
+    }
+}
+
+/// `| status | progress u8 | files u32 | failed u32 |` 进度百分比、已删除和删除失败的文件数
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClearTfResp {
+    pub status: CmdStatus,
+    pub progress: u8,
+    pub files: u32,
+    pub failed: u32,
+}
+
+impl CmdPayload for ClearTfResp {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        Ok(Self {
+            status: r.status()?,
+            progress: r.u8()?,
+            files: r.u32()?,
+            failed: r.u32()?,
+        })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u8(self.status.into());
+        out.put_u8(self.progress);
+        out.put_u32(self.files);
+        out.put_u32(self.failed);
+    }
+}
+
+/// `| status | ota_status u8 | progress u8 | slot str8 |`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OtaResp {
+    pub status: CmdStatus,
+    pub ota_status: u8,
+    pub progress: u8,
+    pub slot: String,
+}
+
+impl CmdPayload for OtaResp {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        Ok(Self {
+            status: r.status()?,
+            ota_status: r.u8()?,
+            progress: r.u8()?,
+            slot: r.str8("slot")?,
+        })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u8(self.status.into());
+        out.put_u8(self.ota_status);
+        out.put_u8(self.progress);
+        put_str8(out, &self.slot);
+    }
+}
+
+/// `| status | firmware str8 | crate str8 | build_time str8 | soc str8 |
+/// protocol u16 | fw_mismatch u8 |`
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionResp {
+    pub status: CmdStatus,
+    pub firmware: String,
+    pub crate_version: String,
+    pub build_time: String,
+    pub soc: String,
+    pub protocol: u16,
+    pub fw_mismatch: bool,
+}
+
+impl CmdPayload for VersionResp {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        Ok(Self {
+            status: r.status()?,
+            firmware: r.str8("firmware")?,
+            crate_version: r.str8("crate_version")?,
+            build_time: r.str8("build_time")?,
+            soc: r.str8("soc")?,
+            protocol: r.u16()?,
+            fw_mismatch: r.bool("fw_mismatch")?,
+        })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u8(self.status.into());
+        put_str8(out, &self.firmware);
+        put_str8(out, &self.crate_version);
+        put_str8(out, &self.build_time);
+        put_str8(out, &self.soc);
+        out.put_u16(self.protocol);
+        out.put_u8(self.fw_mismatch.into());
+    }
+}
+
+/// `| status | time_s u32 | time_zone i8 |` 实际生效的时间
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetTimeResp {
+    pub status: CmdStatus,
+    pub time_s: u32,
+    pub time_zone: i8,
+}
+
+impl CmdPayload for SetTimeResp {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        Ok(Self {
+            status: r.status()?,
+            time_s: r.u32()?,
+            time_zone: r.u8()? as i8,
+        })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u8(self.status.into());
+        out.put_u32(self.time_s);
+        out.put_u8(self.time_zone as u8);
+    }
+}
+
+/// `| status | ip [4] | port u16 |`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SockAddrResp {
+    pub status: CmdStatus,
+    pub addr: SockAddrReq,
+}
+
+impl CmdPayload for SockAddrResp {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        Ok(Self {
+            status: r.status()?,
+            addr: SockAddrReq {
+                ip: r.ipv4()?,
+                port: r.u16()?,
+            },
+        })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u8(self.status.into());
+        self.addr.write(out);
+    }
+}
+
+/// `| status | direction u8 |`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoordinateResp {
+    pub status: CmdStatus,
+    pub direction: Direction,
+}
+
+impl CmdPayload for CoordinateResp {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        Ok(Self {
+            status: r.status()?,
+            direction: Direction::try_from(r.u8()?).map_err(|_| PayloadError::BadValue("direction"))?,
+        })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u8(self.status.into());
+        out.put_u8(self.direction.into());
+    }
+}
+
+/// `| status | count u8 | (key str8, value str8) ... |` 生效后的配置
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config28181Resp {
+    pub status: CmdStatus,
+    pub config: Config28181Items,
+}
+
+impl CmdPayload for Config28181Resp {
+    fn read(r: &mut Reader) -> Result<Self, PayloadError> {
+        Ok(Self {
+            status: r.status()?,
+            config: Config28181Items::read(r)?,
+        })
+    }
+    fn write(&self, out: &mut Vec<u8>) {
+        out.put_u8(self.status.into());
+        self.config.write(out);
+    }
+}
+
+/// A decoded request of any command type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CmdRequest {
+    RemoteCapture(ChannelReq),
+    VideoTape(VideoTapeReq),
+    RetransmissionDocument(RetransmitReq),
+    OpenRtmpMode(RtmpOpenReq),
+    CloseRtmpMode(ChannelReq),
+    Config28181(Config28181Items),
+    DeepSleep(DeepSleepReq),
+    Reserved1(RawReq),
+    Reserved2(RawReq),
+    ClearTfCardFiles(ClearTfReq),
+    HeartBeat(RawReq),
+    Ota(EmptyReq),
+    Version(EmptyReq),
+    SetTime(SetTimeReq),
+    VideoOn(ChannelReq),
+    VideoOff(ChannelReq),
+    SetFlip(SetFlipReq),
+    SetCarCode(SetCarCodeReq),
+    SetIp(SetIpReq),
+    SetSockIpPort(SockAddrReq),
+    SetCoordinate(SetCoordinateReq),
+}
+
+impl CmdRequest {
+    pub fn decode(cmd_type: CmdType, data: &[u8]) -> Result<Self, PayloadError> {
+        use CmdRequest as R;
+        Ok(match cmd_type {
+            CmdType::RemoteCapture => R::RemoteCapture(ChannelReq::decode(data)?),
+            CmdType::VideoTape => R::VideoTape(VideoTapeReq::decode(data)?),
+            CmdType::RetransmissionDocument => R::RetransmissionDocument(RetransmitReq::decode(data)?),
+            CmdType::OpenRtmpMode => R::OpenRtmpMode(RtmpOpenReq::decode(data)?),
+            CmdType::CloseRtmpMode => R::CloseRtmpMode(ChannelReq::decode(data)?),
+            CmdType::Config28181 => R::Config28181(Config28181Items::decode(data)?),
+            CmdType::DeepSleep => R::DeepSleep(DeepSleepReq::decode(data)?),
+            CmdType::Reserved1 => R::Reserved1(RawReq::decode(data)?),
+            CmdType::Reserved2 => R::Reserved2(RawReq::decode(data)?),
+            CmdType::ClearTfCardFiles => R::ClearTfCardFiles(ClearTfReq::decode(data)?),
+            CmdType::HeartBeat => R::HeartBeat(RawReq::decode(data)?),
+            CmdType::Ota => R::Ota(EmptyReq::decode(data)?),
+            CmdType::Version => R::Version(EmptyReq::decode(data)?),
+            CmdType::SetTime => R::SetTime(SetTimeReq::decode(data)?),
+            CmdType::VideoOn => R::VideoOn(ChannelReq::decode(data)?),
+            CmdType::VideoOff => R::VideoOff(ChannelReq::decode(data)?),
+            CmdType::SetFlip => R::SetFlip(SetFlipReq::decode(data)?),
+            CmdType::SetCarCode => R::SetCarCode(SetCarCodeReq::decode(data)?),
+            CmdType::SetIp => R::SetIp(SetIpReq::decode(data)?),
+            CmdType::SetSockIpPort => R::SetSockIpPort(SockAddrReq::decode(data)?),
+            CmdType::SetCoordinate => R::SetCoordinate(SetCoordinateReq::decode(data)?),
+            _ => return Err(PayloadError::BadValue("cmd_type")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: CmdPayload + PartialEq + std::fmt::Debug>(v: T) {
+        let data = v.encode();
+        assert_eq!(T::decode(&data).unwrap(), v);
+        let mut long = data.clone();
+        long.push(0);
+        assert_eq!(T::decode(&long), Err(PayloadError::TrailingData));
+        if !data.is_empty() {
+            assert_eq!(T::decode(&data[..data.len() - 1]), Err(PayloadError::TooShort));
+        }
+    }
+
+    #[test]
+    fn requests_round_trip() {
+        round_trip(ChannelReq { channel: 1 });
+        round_trip(VideoTapeReq {
+            channel: 0,
+            pre_s: 5,
+            duration_s: 20,
+        });
+        round_trip(RetransmitReq { name: "20251018_120000.jpg".into() });
+        round_trip(RtmpOpenReq {
+            channel: 0,
+            url: "rtmp://example.com/live/1".into(),
+        });
+        round_trip(Config28181Items {
+            items: vec![("serverIp".into(), "10.0.0.1".into()), ("status".into(), "on".into())],
+        });
+        round_trip(DeepSleepReq { seconds: 3600 });
+        round_trip(ClearTfReq { mode: 3 });
+        round_trip(SetTimeReq {
+            time_s: 1_760_000_000,
+            time_zone: -5,
+            write_rtc: true,
+        });
+        round_trip(SetFlipReq {
+            channel: 0,
+            mirror: true,
+            flip: false,
+        });
+        round_trip(SetCarCodeReq { code: "粤B12345".into() });
+        round_trip(SetIpReq {
+            link_type: LinkType::Eth,
+            dhcp: false,
+            ip: Ipv4Addr::new(192, 168, 30, 214),
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+            gateway: Ipv4Addr::new(192, 168, 30, 254),
+        });
+        round_trip(SockAddrReq {
+            ip: Ipv4Addr::new(0, 0, 0, 0),
+            port: 9999,
+        });
+        round_trip(SetCoordinateReq {
+            query: false,
+            direction: Direction::BottomRight,
+        });
+        assert_eq!(EmptyReq::decode(&[]), Ok(EmptyReq));
+        assert_eq!(RawReq::decode(&[1, 2]).unwrap().data, [1, 2]);
+    }
+
+    #[test]
+    fn responses_round_trip() {
+        round_trip(StatusResp { status: CmdStatus::Busy });
+        round_trip(FileResp {
+            status: CmdStatus::Ok,
+            file: "/data/events/a.jpg".into(),
+        });
+        round_trip(ClearTfResp {
+            status: CmdStatus::Ok,
+            progress: 100,
+            files: 42,
+            failed: 3,
+        });
+        round_trip(OtaResp {
+            status: CmdStatus::Ok,
+            ota_status: 2,
+            progress: 100,
+            slot: "b".into(),
+        });
+        round_trip(VersionResp {
+            status: CmdStatus::Ok,
+            firmware: "A612LV-1-V1_0_1".into(),
+            crate_version: "0.1.0".into(),
+            build_time: "2025-10-18 12:00:00".into(),
+            soc: "mc6357".into(),
+            protocol: 1,
+            fw_mismatch: true,
+        });
+        round_trip(SetTimeResp {
+            status: CmdStatus::Ok,
+            time_s: 1_760_000_000,
+            time_zone: 8,
+        });
+        round_trip(SockAddrResp {
+            status: CmdStatus::Ok,
+            addr: SockAddrReq {
+                ip: Ipv4Addr::new(192, 168, 30, 214),
+                port: 9999,
+            },
+        });
+        round_trip(CoordinateResp {
+            status: CmdStatus::Ok,
+            direction: Direction::Center,
+        });
+        round_trip(Config28181Resp {
+            status: CmdStatus::Ok,
+            config: Config28181Items {
+                items: vec![("heartBeat".into(), "60".into())],
+            },
+        });
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let err = |cmd_type, data: &[u8]| CmdRequest::decode(cmd_type, data).unwrap_err();
+        assert_eq!(err(CmdType::SetTime, &[0, 0, 0, 0, 20, 0]), PayloadError::BadValue("time_zone"));
+        assert_eq!(err(CmdType::SetTime, &[0, 0, 0, 0, 8, 2]), PayloadError::BadValue("write_rtc"));
+        assert_eq!(err(CmdType::ClearTfCardFiles, &[4]), PayloadError::BadValue("mode"));
+        assert_eq!(err(CmdType::SetSockIpPort, &[10, 0, 0, 1, 0, 0]), PayloadError::BadValue("port"));
+        let ip = vec![0, 0, 192, 168, 1, 9, 255, 0, 255, 0, 192, 168, 1, 1];
+        assert_eq!(err(CmdType::SetIp, &ip), PayloadError::BadValue("netmask"));
+        let mut ip = vec![0, 0, 192, 168, 1, 9, 255, 255, 255, 0, 192, 168, 2, 1];
+        assert_eq!(err(CmdType::SetIp, &ip), PayloadError::BadValue("gateway"));
+        ip[13] = 9;
+        ip[12] = 1;
+        assert_eq!(err(CmdType::SetIp, &ip), PayloadError::BadValue("gateway"));
+        ip[13] = 1;
+        assert!(CmdRequest::decode(CmdType::SetIp, &ip).is_ok());
+        // DHCP 不检查地址
+        ip[1] = 1;
+        ip[12] = 2;
+        assert!(CmdRequest::decode(CmdType::SetIp, &ip).is_ok());
+        assert_eq!(err(CmdType::SetCarCode, &[0]), PayloadError::BadValue("code"));
+        assert_eq!(err(CmdType::SetCoordinate, &[0, 10]), PayloadError::BadValue("direction"));
+        assert_eq!(err(CmdType::Version, &[1]), PayloadError::TrailingData);
+        assert_eq!(err(CmdType::VersionResp, &[]), PayloadError::BadValue("cmd_type"));
+        assert_eq!(CmdStatus::from(PayloadError::TooShort), CmdStatus::BadLength);
+    }
+}
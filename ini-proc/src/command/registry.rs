@@ -0,0 +1,264 @@
+//! 命令分发
+//!
+//! Each `CmdType` maps to a `CmdHandler`. Handlers run as their own task,
+//! so they may be async and long-running, and reach the shared services
+//! (config, storage, the connection sender) through `CmdContext`.
+//! Acknowledgement model, all sent as CmdResp echoing the request sn:
+//!
+//! - accepted: an immediate ack whose `cmd_type` is the request's own
+//!   (odd) type with body `| status u8 = Accepted |`, sent before the
+//!   handler starts;
+//! - progress: optional, for long-running work; more frames under the
+//!   request's own type whose body is the `*Resp` payload so far, so
+//!   their status is never `Accepted`;
+//! - final: the `*Resp` type with the handler's result, sent when the
+//!   work completes, however long it takes.
+//!
+//! Commands without a handler (Unsupported) and malformed requests of
+//! supported commands (decode error) get no ack, only the final `*Resp`
+//! with the error status. A handler that
+//! panics is answered with `CmdStatus::Failed`.
+
+use super::payload::*;
+use crate::communication::protocol::*;
+use crate::communication::types::*;
+use crate::config::ini_parse;
+use crate::config::validate::ConfigError;
+use crate::media::media_events_dir;
+use crate::storage::emmc;
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, OnceLock, RwLock},
+};
+use tokio::sync::mpsc;
+
+static CMD_REGISTRY: OnceLock<RwLock<HashMap<CmdType, Arc<dyn CmdHandler>>>> = OnceLock::new();
+
+pub type CmdFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+pub trait CmdHandler: Send + Sync {
+    fn handle(&self, ctx: CmdContext, cmd: CmdPackage) -> CmdFuture;
+}
+
+// 普通 async fn 可以直接注册
+impl<F, Fut> CmdHandler for F
+where
+    F: Fn(CmdContext, CmdPackage) -> Fut + Send + Sync,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    fn handle(&self, ctx: CmdContext, cmd: CmdPackage) -> CmdFuture {
+        Box::pin(self(ctx, cmd))
+    }
+}
+
+/// What a handler gets to work with: the request it answers and the
+/// services it may use.
+#[derive(Clone)]
+pub struct CmdContext {
+    pub req: McuComPackageHead,
+    pub cmd_type: CmdType,
+    pub tx: mpsc::Sender<Vec<u8>>,
+}
+
+impl CmdContext {
+    pub fn config(&self, section: &str, key: &str) -> Option<String> {
+        ini_parse::ini_get_ini_config_in(section, key)
+    }
+
+    /// Handlers only write keys they own, so a key missing from an older
+    /// INI is added.
+    pub fn set_config(&self, section: &str, key: &str, value: &str) -> Result<(), ConfigError> {
+        ini_parse::ini_put_ini_config(section, key, value)
+    }
+
+    pub fn save_config(&self) -> Result<(), ConfigError> {
+        ini_parse::ini_save_config()
+    }
+
+    pub fn storage(&self) -> Option<emmc::EmmcStatus> {
+        emmc::emmc_get_info()
+    }
+
+    /// Where event media goes, created if missing.
+    pub fn events_path(&self) -> Option<PathBuf> {
+        media_events_dir().ok()
+    }
+
+    /// Acknowledges the request as accepted.
+    pub(crate) async fn accept(&self) -> Option<u16> {
+        self.progress(&StatusResp {
+            status: CmdStatus::Accepted,
+        })
+        .await
+    }
+
+    /// Reports intermediate state under the request's own type.
+    pub async fn progress<T: CmdPayload>(&self, resp: &T) -> Option<u16> {
+        let cmd = CmdPackage::new(self.cmd_type as u16, &resp.encode());
+        protocol_package_send(ComPackage::Cmd(cmd), McuComMsgType::CmdResp, Some(&self.req), &self.tx).await
+    }
+
+    /// Sends `data` as the response type of the request.
+    pub async fn respond(&self, data: &[u8]) -> Option<u16> {
+        let resp_type = self.cmd_type.response_type().unwrap_or(self.cmd_type);
+        let cmd = CmdPackage::new(resp_type as u16, data);
+        protocol_package_send(ComPackage::Cmd(cmd), McuComMsgType::CmdResp, Some(&self.req), &self.tx).await
+    }
+
+    pub async fn reply<T: CmdPayload>(&self, resp: &T) -> Option<u16> {
+        self.respond(&resp.encode()).await
+    }
+
+    pub async fn respond_status(&self, status: CmdStatus) -> Option<u16> {
+        self.reply(&StatusResp { status }).await
+    }
+}
+
+fn cmd_registry() -> &'static RwLock<HashMap<CmdType, Arc<dyn CmdHandler>>> {
+    CMD_REGISTRY.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// Registers the handler for `cmd_type`, replacing any previous one.
+pub fn cmd_register(cmd_type: CmdType, handler: Arc<dyn CmdHandler>) -> Option<i32> {
+    if cmd_registry().write().ok()?.insert(cmd_type, handler).is_some() {
+        println!("cmd handler for {:?} replaced", cmd_type);
+    }
+    Some(0)
+}
+
+#[cfg(test)]
+pub fn cmd_unregister(cmd_type: CmdType) -> Option<i32> {
+    cmd_registry().write().ok()?.remove(&cmd_type)?;
+    Some(0)
+}
+
+fn cmd_handler(cmd_type: CmdType) -> Option<Arc<dyn CmdHandler>> {
+    cmd_registry().read().ok()?.get(&cmd_type).cloned()
+}
+
+/// Hands a command to its handler task, or answers it as unsupported.
+pub(crate) async fn cmd_dispatch(pack: &McuComPackage, tx: &mpsc::Sender<Vec<u8>>) {
+    let Some(cmd) = pack.as_cmd() else {
+        return;
+    };
+    let cmd_type = match CmdType::try_from(cmd.cmd_type) {
+        Ok(cmd_type) if cmd_type != CmdType::Unknown && !cmd_type.is_response() => cmd_type,
+        _ => {
+            println!("invalid cmd_type: {}", cmd.cmd_type);
+            return;
+        }
+    };
+    let ctx = CmdContext {
+        req: pack.head,
+        cmd_type,
+        tx: tx.clone(),
+    };
+    let Some(handler) = cmd_handler(cmd_type) else {
+        println!("cmd {:?} not supported", cmd_type);
+        ctx.respond_status(CmdStatus::Unsupported).await;
+        return;
+    };
+    if let Err(err) = CmdRequest::decode(cmd_type, &cmd.data) {
+        println!("cmd {:?} payload invalid: {:?}", cmd_type, err);
+        ctx.respond_status(err.into()).await;
+        return;
+    }
+    ctx.accept().await;
+    let work = tokio::spawn(handler.handle(ctx.clone(), cmd.clone()));
+    tokio::spawn(async move {
+        if let Err(err) = work.await {
+            println!("cmd {:?} handler failed: {}", ctx.cmd_type, err);
+            ctx.respond_status(CmdStatus::Failed).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(sn: u16, cmd_type: CmdType) -> McuComPackage {
+        McuComPackage {
+            head: McuComPackageHead {
+                pack_head_flg: PACKAGE_HEAD_FLAG,
+                data_len: 0,
+                crc: 0,
+                mcu_id: 2,
+                sn,
+                src_sn: 0,
+                msg_type: McuComMsgType::Cmd as u16,
+            },
+            data: ComPackage::Cmd(CmdPackage::new(cmd_type as u16, b"ping")),
+        }
+    }
+
+    async fn reply(rx: &mut mpsc::Receiver<Vec<u8>>) -> CmdPackage {
+        let ParseResult::Success(pack) = parse_package_head(&rx.recv().await.unwrap()) else {
+            panic!("bad frame");
+        };
+        pack.as_cmd().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn registered_handler_runs_and_others_are_unsupported() {
+        cmd_register(
+            CmdType::Reserved1,
+            Arc::new(|ctx: CmdContext, cmd: CmdPackage| async move {
+                ctx.respond(&cmd.data).await;
+            }),
+        );
+        let (tx, mut rx) = mpsc::channel(8);
+        cmd_dispatch(&request(301, CmdType::Reserved1), &tx).await;
+        let ack = reply(&mut rx).await;
+        assert_eq!(ack.cmd_type, CmdType::Reserved1 as u16);
+        assert_eq!(ack.data, [CmdStatus::Accepted as u8]);
+        let resp = reply(&mut rx).await;
+        assert_eq!(resp.cmd_type, CmdType::Reserved1Resp as u16);
+        assert_eq!(resp.data, b"ping");
+
+        cmd_dispatch(&request(302, CmdType::Reserved2), &tx).await;
+        let resp = reply(&mut rx).await;
+        assert_eq!(resp.cmd_type, CmdType::Reserved2Resp as u16);
+        assert_eq!(resp.data, [CmdStatus::Unsupported as u8]);
+        cmd_unregister(CmdType::Reserved1);
+
+        // 未注册的命令不解析负载，直接 Unsupported
+        let mut bad = request(303, CmdType::ClearTfCardFiles);
+        bad.data = ComPackage::Cmd(CmdPackage::new(CmdType::ClearTfCardFiles as u16, &[9]));
+        cmd_dispatch(&bad, &tx).await;
+        let resp = reply(&mut rx).await;
+        assert_eq!(resp.cmd_type, CmdType::ClearTfCardFilesResp as u16);
+        assert_eq!(resp.data, [CmdStatus::Unsupported as u8]);
+
+        cmd_register(
+            CmdType::ClearTfCardFiles,
+            Arc::new(|_ctx: CmdContext, _cmd: CmdPackage| async move {}),
+        );
+        bad.head.sn = 305;
+        cmd_dispatch(&bad, &tx).await;
+        let resp = reply(&mut rx).await;
+        assert_eq!(resp.cmd_type, CmdType::ClearTfCardFilesResp as u16);
+        assert_eq!(resp.data, [CmdStatus::BadValue as u8]);
+        cmd_unregister(CmdType::ClearTfCardFiles);
+    }
+
+    #[tokio::test]
+    async fn panicking_handler_gets_failed_response() {
+        cmd_register(
+            CmdType::HeartBeat,
+            Arc::new(|_ctx: CmdContext, _cmd: CmdPackage| async move {
+                panic!("handler bug");
+            }),
+        );
+        let (tx, mut rx) = mpsc::channel(8);
+        cmd_dispatch(&request(304, CmdType::HeartBeat), &tx).await;
+        assert_eq!(reply(&mut rx).await.cmd_type, CmdType::HeartBeat as u16);
+        let resp = reply(&mut rx).await;
+        assert_eq!(resp.cmd_type, CmdType::HeartBeatResp as u16);
+        assert_eq!(resp.data, [CmdStatus::Failed as u8]);
+        cmd_unregister(CmdType::HeartBeat);
+    }
+}
@@ -0,0 +1,90 @@
+//! 远程抓拍
+//!
+//! RemoteCapture takes one snapshot of the requested channel and replies
+//! with its file name, the same name RetransmissionDocument accepts.
+
+use super::payload::*;
+use super::registry::*;
+use crate::communication::types::*;
+use crate::media::capture::*;
+use std::{path::PathBuf, sync::Arc};
+
+#[derive(Clone)]
+pub struct RemoteCaptureHandler {
+    backend: Arc<dyn CaptureBackend>,
+}
+
+impl RemoteCaptureHandler {
+    pub fn new(backend: Arc<dyn CaptureBackend>) -> Self {
+        Self { backend }
+    }
+
+    async fn run(&self, req: ChannelReq, dir: PathBuf) -> FileResp {
+        if !capture_channel_valid(req.channel) {
+            return FileResp {
+                status: CmdStatus::InvalidParam,
+                file: String::new(),
+            };
+        }
+        let backend = self.backend.clone();
+        let shot = tokio::task::spawn_blocking(move || capture_snapshot(backend.as_ref(), &dir, req.channel)).await;
+        match shot {
+            Ok(Ok(path)) => FileResp {
+                status: CmdStatus::Ok,
+                file: path.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_string(),
+            },
+            Ok(Err(err)) => {
+                println!("remote capture ch{} failed: {:?}", req.channel, err);
+                FileResp {
+                    status: CmdStatus::Failed,
+                    file: String::new(),
+                }
+            }
+            Err(_) => FileResp {
+                status: CmdStatus::Failed,
+                file: String::new(),
+            },
+        }
+    }
+}
+
+impl CmdHandler for RemoteCaptureHandler {
+    fn handle(&self, ctx: CmdContext, cmd: CmdPackage) -> CmdFuture {
+        let this = self.clone();
+        Box::pin(async move {
+            let resp = match (ChannelReq::decode(&cmd.data), ctx.events_path()) {
+                (Ok(req), Some(dir)) => this.run(req, dir).await,
+                (Ok(_), None) => FileResp {
+                    status: CmdStatus::Failed,
+                    file: String::new(),
+                },
+                (Err(err), _) => FileResp {
+                    status: err.into(),
+                    file: String::new(),
+                },
+            };
+            ctx.reply(&resp).await;
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::capture::tests::file_capture;
+    use crate::media::tests::media_test_dir;
+
+    #[tokio::test]
+    async fn reply_carries_file_name() {
+        let handler = RemoteCaptureHandler::new(Arc::new(file_capture(1)));
+        let dir = media_test_dir("remote-capture");
+        let resp = handler.run(ChannelReq { channel: 0 }, dir.clone()).await;
+        assert_eq!(resp.status, CmdStatus::Ok);
+        assert!(resp.file.ends_with("_ch0.jpg") && !resp.file.contains('/'));
+        assert!(dir.join(&resp.file).exists());
+
+        assert_eq!(handler.run(ChannelReq { channel: 1 }, dir.clone()).await.status, CmdStatus::Failed);
+        assert_eq!(handler.run(ChannelReq { channel: 9 }, dir.clone()).await.status, CmdStatus::InvalidParam);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
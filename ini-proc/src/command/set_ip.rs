@@ -0,0 +1,223 @@
+//! 设置 IP
+//!
+//! SetIp applies the new address configuration, then waits up to
+//! `confirm_timeout` for the backend to confirm connectivity. Only a
+//! confirmed configuration is persisted; otherwise the previous one is
+//! re-applied and the command fails. When the listener follows
+//! `network/local_ip` (no `listen_ip` set) and the static address
+//! changes, it is moved to the new address as part of the confirmation
+//! and the sessions on the old address are closed after the answer.
+
+use super::payload::*;
+use super::registry::*;
+use crate::communication::tcp_transport::{tcp_listen_addr, tcp_server_close_sessions, tcp_server_rebind};
+use crate::communication::types::*;
+use crate::config::ini_parse;
+use crate::system::network::*;
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
+const CONFIRM_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Clone)]
+pub struct SetIpHandler {
+    net: Arc<dyn NetworkBackend>,
+    confirm_timeout: Duration,
+    confirm_interval: Duration,
+}
+
+impl SetIpHandler {
+    pub fn new(net: Arc<dyn NetworkBackend>) -> Self {
+        Self {
+            net,
+            confirm_timeout: CONFIRM_TIMEOUT,
+            confirm_interval: CONFIRM_INTERVAL,
+        }
+    }
+}
+
+async fn set_ip_confirm(net: &Arc<dyn NetworkBackend>, cfg: &NetConfig, timeout: Duration, interval: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let (n, c) = (net.clone(), cfg.clone());
+        if tokio::task::spawn_blocking(move || n.check(&c)).await.unwrap_or(false) {
+            return true;
+        }
+        if Instant::now() + interval > deadline {
+            return false;
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn set_ip_apply(net: &Arc<dyn NetworkBackend>, cfg: &NetConfig) -> bool {
+    let (n, c) = (net.clone(), cfg.clone());
+    match tokio::task::spawn_blocking(move || n.apply(&c)).await {
+        Ok(Ok(())) => true,
+        Ok(Err(err)) => {
+            println!("network apply {:?} failed: {:#}", cfg, err);
+            false
+        }
+        Err(_) => false,
+    }
+}
+
+/// 监听跟随 local_ip 且静态地址改变时，返回新的监听地址
+fn set_ip_listen_addr(cfg: &NetConfig) -> Option<SocketAddr> {
+    if cfg.dhcp || ini_parse::ini_get_ini_config("network", "listen_ip").is_some() {
+        return None;
+    }
+    let current = tcp_listen_addr()?;
+    let addr = SocketAddr::from((cfg.ip, current.port()));
+    (addr != current).then_some(addr)
+}
+
+impl SetIpHandler {
+    /// Returns the status and whether the listener was moved.
+    async fn run(&self, req: SetIpReq) -> (CmdStatus, bool) {
+        let cfg = NetConfig {
+            link_type: req.link_type,
+            dhcp: req.dhcp,
+            ip: req.ip,
+            netmask: req.netmask,
+            gateway: req.gateway,
+        };
+        // 读不到原配置就无法回滚, 不冒险修改
+        let Some(previous) = network_config_load() else {
+            println!("network config incomplete, refuse SetIp {:?}", cfg);
+            return (CmdStatus::Failed, false);
+        };
+        let listen = set_ip_listen_addr(&cfg);
+        let confirmed = set_ip_apply(&self.net, &cfg).await
+            && set_ip_confirm(&self.net, &cfg, self.confirm_timeout, self.confirm_interval).await;
+        // 新地址生效后才能绑定，失败时和未确认一样回滚
+        let rebound = match listen {
+            Some(addr) if confirmed => tcp_server_rebind(addr).await.is_some(),
+            _ => false,
+        };
+        if !confirmed || (listen.is_some() && !rebound) {
+            println!("network {:?} not confirmed, roll back to {:?}", cfg, previous);
+            set_ip_apply(&self.net, &previous).await;
+            return (CmdStatus::Failed, false);
+        }
+        match network_config_save(&cfg) {
+            Ok(()) => (CmdStatus::Ok, rebound),
+            Err(err) => {
+                println!("network config save failed: {:?}", err);
+                (CmdStatus::Failed, rebound)
+            }
+        }
+    }
+}
+
+impl CmdHandler for SetIpHandler {
+    fn handle(&self, ctx: CmdContext, cmd: CmdPackage) -> CmdFuture {
+        let this = self.clone();
+        Box::pin(async move {
+            let (status, rebound) = match SetIpReq::decode(&cmd.data) {
+                Ok(req) => this.run(req).await,
+                Err(err) => (err.into(), false),
+            };
+            // 应答先进入发送队列，关闭会话时会先发完
+            ctx.respond_status(status).await;
+            if rebound {
+                tcp_server_close_sessions().await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ini_parse::{ini_get_ini_config, ini_put_ini_config, ini_test_init};
+    use anyhow::Result;
+    use std::net::Ipv4Addr;
+    use std::sync::Mutex;
+
+    struct MockNetwork {
+        applied: Mutex<Vec<NetConfig>>,
+        reachable: bool,
+    }
+
+    impl NetworkBackend for MockNetwork {
+        fn apply(&self, cfg: &NetConfig) -> Result<()> {
+            self.applied.lock().unwrap().push(cfg.clone());
+            Ok(())
+        }
+        fn check(&self, _cfg: &NetConfig) -> bool {
+            self.reachable
+        }
+    }
+
+    fn handler(net: Arc<MockNetwork>) -> SetIpHandler {
+        SetIpHandler {
+            net,
+            confirm_timeout: Duration::from_millis(50),
+            confirm_interval: Duration::from_millis(10),
+        }
+    }
+
+    fn req(ip: [u8; 4]) -> SetIpReq {
+        SetIpReq {
+            link_type: LinkType::Eth,
+            dhcp: false,
+            ip: Ipv4Addr::from(ip),
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+            gateway: Ipv4Addr::new(ip[0], ip[1], ip[2], 1),
+        }
+    }
+
+    // 两种情况共享全局配置，放在一个测试里顺序执行
+    #[tokio::test]
+    async fn confirmed_config_is_saved_and_unconfirmed_is_rolled_back() {
+        ini_test_init();
+        // 监听跟随 local_ip 时地址改变需要移动监听，未启动服务时移动失败
+        ini_put_ini_config("network", "local_ip", "10.1.2.3").unwrap();
+        let cfg = |r: SetIpReq| NetConfig {
+            link_type: r.link_type,
+            dhcp: r.dhcp,
+            ip: r.ip,
+            netmask: r.netmask,
+            gateway: r.gateway,
+        };
+        assert_eq!(
+            set_ip_listen_addr(&cfg(req([10, 1, 2, 4]))),
+            Some(SocketAddr::from(([10, 1, 2, 4], 9999)))
+        );
+        assert_eq!(set_ip_listen_addr(&cfg(req([10, 1, 2, 3]))), None);
+        let net = Arc::new(MockNetwork {
+            applied: Mutex::new(Vec::new()),
+            reachable: true,
+        });
+        assert_eq!(handler(net.clone()).run(req([10, 1, 2, 4])).await, (CmdStatus::Failed, false));
+        assert_eq!(net.applied.lock().unwrap().len(), 2);
+        assert_eq!(ini_get_ini_config("network", "local_ip").as_deref(), Some("10.1.2.3"));
+
+        // 单独设置了监听地址时不跟随
+        ini_put_ini_config("network", "listen_ip", "0.0.0.0").unwrap();
+        assert_eq!(set_ip_listen_addr(&cfg(req([10, 1, 2, 4]))), None);
+        let net = Arc::new(MockNetwork {
+            applied: Mutex::new(Vec::new()),
+            reachable: true,
+        });
+        assert_eq!(handler(net.clone()).run(req([10, 1, 2, 3])).await, (CmdStatus::Ok, false));
+        assert_eq!(net.applied.lock().unwrap().len(), 1);
+        assert_eq!(ini_get_ini_config("network", "local_ip").as_deref(), Some("10.1.2.3"));
+        assert_eq!(ini_get_ini_config("network", "local_gateway").as_deref(), Some("10.1.2.1"));
+
+        let net = Arc::new(MockNetwork {
+            applied: Mutex::new(Vec::new()),
+            reachable: false,
+        });
+        assert_eq!(handler(net.clone()).run(req([172, 16, 0, 9])).await, (CmdStatus::Failed, false));
+        let applied = net.applied.lock().unwrap();
+        assert_eq!(applied.len(), 2);
+        assert_eq!(applied[1].ip, Ipv4Addr::new(10, 1, 2, 3));
+        assert_eq!(ini_get_ini_config("network", "local_ip").as_deref(), Some("10.1.2.3"));
+    }
+}
@@ -0,0 +1,87 @@
+//! 设置 Socket 监听地址
+//!
+//! SetSockIpPort binds the new address first and only then releases the
+//! old listener, persists `network/listen_ip` and `listen_port`, and
+//! answers on the current connection before closing the sessions on the
+//! old address. If the bind fails the old listener keeps serving and the
+//! INI is left alone.
+
+use super::payload::*;
+use super::registry::*;
+use crate::communication::tcp_transport::{tcp_listen_addr, tcp_server_close_sessions, tcp_server_rebind};
+use crate::communication::types::*;
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::net::TcpListener;
+
+/// 地址必须属于本机: 用临时端口试绑定一次
+async fn set_sock_ip_is_local(ip: Ipv4Addr) -> bool {
+    ip.is_unspecified() || TcpListener::bind((ip, 0)).await.is_ok()
+}
+
+pub(crate) async fn set_sock_cmd(ctx: CmdContext, cmd: CmdPackage) {
+    let current = tcp_listen_addr().unwrap_or(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)));
+    let resp = |status, addr: SocketAddr| SockAddrResp {
+        status,
+        addr: SockAddrReq {
+            ip: match addr.ip() {
+                std::net::IpAddr::V4(ip) => ip,
+                std::net::IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+            },
+            port: addr.port(),
+        },
+    };
+    let req = match SockAddrReq::decode(&cmd.data) {
+        Ok(req) => req,
+        Err(err) => {
+            ctx.reply(&resp(err.into(), current)).await;
+            return;
+        }
+    };
+    let addr = SocketAddr::from((req.ip, req.port));
+    if !set_sock_ip_is_local(req.ip).await {
+        println!("listen ip {} is not local", req.ip);
+        ctx.reply(&resp(CmdStatus::InvalidParam, current)).await;
+        return;
+    }
+    if addr == current {
+        ctx.reply(&resp(CmdStatus::Ok, current)).await;
+        return;
+    }
+    if tcp_server_rebind(addr).await.is_none() {
+        println!("rebind {} failed, keep {}", addr, current);
+        ctx.reply(&resp(CmdStatus::Failed, current)).await;
+        return;
+    }
+    let saved = ctx
+        .set_config("network", "listen_ip", &req.ip.to_string())
+        .and_then(|_| ctx.set_config("network", "listen_port", &req.port.to_string()))
+        .and_then(|_| ctx.save_config());
+    if let Err(err) = saved {
+        // 重启后仍会用旧地址，内存配置和监听都退回去保持一致
+        println!("save listen addr failed: {:?}", err);
+        let _ = ctx
+            .set_config("network", "listen_ip", &current.ip().to_string())
+            .and_then(|_| ctx.set_config("network", "listen_port", &current.port().to_string()));
+        if tcp_server_rebind(current).await.is_none() {
+            println!("rebind back to {} failed", current);
+        }
+        ctx.reply(&resp(CmdStatus::Failed, current)).await;
+        return;
+    }
+    // 应答先进入发送队列，关闭会话时会先发完
+    ctx.reply(&resp(CmdStatus::Ok, addr)).await;
+    tcp_server_close_sessions().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_local_addresses_are_accepted() {
+        assert!(set_sock_ip_is_local(Ipv4Addr::UNSPECIFIED).await);
+        assert!(set_sock_ip_is_local(Ipv4Addr::LOCALHOST).await);
+        // TEST-NET-3，不会配置在本机
+        assert!(!set_sock_ip_is_local(Ipv4Addr::new(203, 0, 113, 7)).await);
+    }
+}
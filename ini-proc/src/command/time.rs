@@ -0,0 +1,159 @@
+//! 校时
+//!
+//! SetTime sets the system clock (and optionally the RTC) to the MCU's
+//! time, persists the timezone, and replies with the applied time.
+
+use super::payload::*;
+use super::registry::*;
+use crate::communication::types::*;
+use crate::system::clock::ClockBackend;
+use std::sync::Arc;
+
+pub struct SetTimeHandler {
+    clock: Arc<dyn ClockBackend>,
+}
+
+impl SetTimeHandler {
+    pub fn new(clock: Arc<dyn ClockBackend>) -> Self {
+        Self { clock }
+    }
+}
+
+fn set_time_apply(clock: &dyn ClockBackend, req: &SetTimeReq) -> SetTimeResp {
+    let mut status = CmdStatus::Ok;
+    if let Err(err) = clock.set_time(req.time_s) {
+        println!("set time {} failed: {:#}", req.time_s, err);
+        status = CmdStatus::Failed;
+    } else if req.write_rtc
+        && let Err(err) = clock.write_rtc()
+    {
+        println!("write rtc failed: {:#}", err);
+        status = CmdStatus::Failed;
+    }
+    if let Err(err) = clock.set_time_zone(req.time_zone) {
+        println!("set time zone {} failed: {:#}", req.time_zone, err);
+        status = CmdStatus::Failed;
+    }
+    SetTimeResp {
+        status,
+        time_s: clock.now(),
+        time_zone: req.time_zone,
+    }
+}
+
+impl CmdHandler for SetTimeHandler {
+    fn handle(&self, ctx: CmdContext, cmd: CmdPackage) -> CmdFuture {
+        let clock = self.clock.clone();
+        Box::pin(async move {
+            let req = match SetTimeReq::decode(&cmd.data) {
+                Ok(req) => req,
+                Err(err) => {
+                    ctx.respond_status(err.into()).await;
+                    return;
+                }
+            };
+            // hwclock 和写配置文件都是阻塞操作
+            let resp = tokio::task::spawn_blocking(move || set_time_apply(clock.as_ref(), &req)).await;
+            match resp {
+                Ok(resp) => ctx.reply(&resp).await,
+                Err(_) => ctx.respond_status(CmdStatus::Failed).await,
+            };
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communication::protocol::*;
+    use anyhow::{Result, bail};
+    use std::sync::Mutex;
+    use tokio::sync::mpsc;
+
+    #[derive(Default)]
+    struct MockClock {
+        time_s: Mutex<u32>,
+        rtc: Mutex<Option<u32>>,
+        time_zone: Mutex<Option<i8>>,
+        rtc_broken: bool,
+    }
+
+    impl ClockBackend for MockClock {
+        fn now(&self) -> u32 {
+            *self.time_s.lock().unwrap()
+        }
+        fn set_time(&self, time_s: u32) -> Result<()> {
+            *self.time_s.lock().unwrap() = time_s;
+            Ok(())
+        }
+        fn write_rtc(&self) -> Result<()> {
+            if self.rtc_broken {
+                bail!("no rtc");
+            }
+            *self.rtc.lock().unwrap() = Some(self.now());
+            Ok(())
+        }
+        fn set_time_zone(&self, time_zone: i8) -> Result<()> {
+            *self.time_zone.lock().unwrap() = Some(time_zone);
+            Ok(())
+        }
+    }
+
+    async fn set_time(clock: Arc<MockClock>, req: SetTimeReq) -> SetTimeResp {
+        let (tx, mut rx) = mpsc::channel(8);
+        let ctx = CmdContext {
+            req: McuComPackageHead {
+                pack_head_flg: PACKAGE_HEAD_FLAG,
+                data_len: 0,
+                crc: 0,
+                mcu_id: 2,
+                sn: 60,
+                src_sn: 0,
+                msg_type: McuComMsgType::Cmd as u16,
+            },
+            cmd_type: CmdType::SetTime,
+            tx,
+        };
+        let cmd = CmdPackage::new(CmdType::SetTime as u16, &req.encode());
+        SetTimeHandler::new(clock).handle(ctx, cmd).await;
+        let ParseResult::Success(pack) = parse_package_head(&rx.recv().await.unwrap()) else {
+            panic!("bad frame");
+        };
+        let resp = pack.as_cmd().unwrap();
+        assert_eq!(resp.cmd_type, CmdType::SetTimeResp as u16);
+        SetTimeResp::decode(&resp.data).unwrap()
+    }
+
+    #[tokio::test]
+    async fn time_rtc_and_zone_are_applied() {
+        let clock = Arc::new(MockClock::default());
+        let req = SetTimeReq {
+            time_s: 1_760_000_000,
+            time_zone: -3,
+            write_rtc: true,
+        };
+        let resp = set_time(clock.clone(), req).await;
+        assert_eq!(resp.status, CmdStatus::Ok);
+        assert_eq!(resp.time_s, 1_760_000_000);
+        assert_eq!(resp.time_zone, -3);
+        assert_eq!(*clock.rtc.lock().unwrap(), Some(1_760_000_000));
+        assert_eq!(*clock.time_zone.lock().unwrap(), Some(-3));
+    }
+
+    #[tokio::test]
+    async fn rtc_failure_is_reported() {
+        let clock = Arc::new(MockClock {
+            rtc_broken: true,
+            ..Default::default()
+        });
+        let req = SetTimeReq {
+            time_s: 1_760_000_100,
+            time_zone: 8,
+            write_rtc: true,
+        };
+        let resp = set_time(clock.clone(), req).await;
+        assert_eq!(resp.status, CmdStatus::Failed);
+        // 系统时间已经生效
+        assert_eq!(resp.time_s, 1_760_000_100);
+    }
+}
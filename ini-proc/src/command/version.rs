@@ -0,0 +1,65 @@
+//! 版本查询
+//!
+//! Reports the firmware, crate and protocol versions, the build time and
+//! the SoC. `fw_mismatch` is set when the INI's `FW_VERSION` differs from
+//! the running binary, which happens after a partial upgrade.
+
+use super::payload::*;
+use super::registry::*;
+use crate::common::FW_VERSION;
+use crate::communication::protocol::PROTOCOL_VERSION;
+use crate::communication::types::*;
+use chrono::DateTime;
+
+const BUILD_TIMESTAMP: &str = env!("BUILD_TIMESTAMP");
+
+pub fn version_build_time() -> String {
+    BUILD_TIMESTAMP
+        .parse::<i64>()
+        .ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .map_or_else(|| BUILD_TIMESTAMP.to_string(), |t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+}
+
+pub(crate) fn version_info(ini_fw_version: Option<&str>, soc: Option<&str>) -> VersionResp {
+    let fw_mismatch = ini_fw_version.is_some_and(|v| v != FW_VERSION);
+    if fw_mismatch {
+        println!("FW_VERSION mismatch, ini: {:?}, binary: {}", ini_fw_version, FW_VERSION);
+    }
+    VersionResp {
+        status: CmdStatus::Ok,
+        firmware: FW_VERSION.to_string(),
+        crate_version: env!("CARGO_PKG_VERSION").to_string(),
+        build_time: version_build_time(),
+        soc: soc.unwrap_or_default().to_string(),
+        protocol: PROTOCOL_VERSION,
+        fw_mismatch,
+    }
+}
+
+pub(crate) async fn version_cmd(ctx: CmdContext, _cmd: CmdPackage) {
+    let ini_fw_version = ctx.config("system", "FW_VERSION");
+    let soc = ctx.config("", "soc");
+    ctx.reply(&version_info(ini_fw_version.as_deref(), soc.as_deref())).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_reports_mismatch() {
+        let info = version_info(Some(FW_VERSION), Some("mc6357"));
+        assert!(!info.fw_mismatch);
+        assert_eq!(info.soc, "mc6357");
+        assert_eq!(info.crate_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.protocol, PROTOCOL_VERSION);
+        assert!(info.build_time.ends_with("UTC"));
+
+        let info = version_info(Some("A612LV-1-V0_9_0-250101"), None);
+        assert!(info.fw_mismatch);
+        assert_eq!(info.firmware, FW_VERSION);
+        // 应答必须能放进一帧
+        assert!(info.encode().len() <= CMD_DATA_MAX);
+    }
+}
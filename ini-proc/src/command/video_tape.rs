@@ -0,0 +1,142 @@
+//! 事件录像命令
+//!
+//! VideoTape records `pre_s` seconds from the continuous recording plus
+//! `duration_s` live seconds of a channel, and replies with the clip's
+//! file name once it is complete. Event-triggered clips use the INI
+//! `system/clip_pre_s` and `clip_duration_s` instead.
+
+use super::payload::*;
+use super::registry::*;
+use crate::communication::types::*;
+use crate::config::ini_parse;
+use crate::media::clip::*;
+use crate::media::media_events_dir;
+use crate::storage::emmc::VIDEO_DEVICE_MAX_COUNT;
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+#[derive(Clone)]
+pub struct VideoTapeHandler {
+    backend: Arc<dyn ClipBackend>,
+}
+
+impl VideoTapeHandler {
+    pub fn new(backend: Arc<dyn ClipBackend>) -> Self {
+        Self { backend }
+    }
+
+    async fn run(&self, req: VideoTapeReq, dir: PathBuf) -> FileResp {
+        let backend = self.backend.clone();
+        let pre = Duration::from_secs(req.pre_s.into());
+        let duration = Duration::from_secs(req.duration_s.into());
+        let clip = tokio::task::spawn_blocking(move || clip_record(backend.as_ref(), &dir, req.channel, pre, duration)).await;
+        let (status, file) = match clip {
+            Ok(Ok(path)) => (
+                CmdStatus::Ok,
+                path.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_string(),
+            ),
+            Ok(Err(ClipError::InvalidParam)) => (CmdStatus::InvalidParam, String::new()),
+            Ok(Err(ClipError::Busy)) => (CmdStatus::Busy, String::new()),
+            Ok(Err(ClipError::Failed)) | Err(_) => (CmdStatus::Failed, String::new()),
+        };
+        FileResp { status, file }
+    }
+}
+
+impl CmdHandler for VideoTapeHandler {
+    fn handle(&self, ctx: CmdContext, cmd: CmdPackage) -> CmdFuture {
+        let this = self.clone();
+        Box::pin(async move {
+            let resp = match (VideoTapeReq::decode(&cmd.data), ctx.events_path()) {
+                (Ok(req), Some(dir)) => this.run(req, dir).await,
+                (Ok(_), None) => FileResp {
+                    status: CmdStatus::Failed,
+                    file: String::new(),
+                },
+                (Err(err), _) => FileResp {
+                    status: err.into(),
+                    file: String::new(),
+                },
+            };
+            ctx.reply(&resp).await;
+        })
+    }
+}
+
+fn video_tape_config_secs(key: &str, default: u64) -> Duration {
+    let secs = ini_parse::ini_get_ini_config("system", key)
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default);
+    Duration::from_secs(secs)
+}
+
+/// 事件录像: 所有接了摄像头的通道各录一段
+pub fn video_tape_event_clips(backend: &dyn ClipBackend) -> Vec<PathBuf> {
+    let pre = video_tape_config_secs("clip_pre_s", 5);
+    let duration = video_tape_config_secs("clip_duration_s", 15);
+    let dir = match media_events_dir() {
+        Ok(dir) => dir,
+        Err(err) => {
+            println!("event clip: no events dir: {}", err);
+            return Vec::new();
+        }
+    };
+    let dir = dir.as_path();
+    let channels: Vec<u8> = (0..VIDEO_DEVICE_MAX_COUNT as u8)
+        .filter(|&channel| backend.available(channel))
+        .collect();
+    // 各通道同时录制，事件时刻对齐
+    std::thread::scope(|s| {
+        let clips: Vec<_> = channels
+            .into_iter()
+            .map(|channel| {
+                s.spawn(move || match clip_record(backend, dir, channel, pre, duration) {
+                    Ok(path) => Some(path),
+                    Err(err) => {
+                        println!("event clip ch{} failed: {:?}", channel, err);
+                        None
+                    }
+                })
+            })
+            .collect();
+        clips.into_iter().filter_map(|c| c.join().ok().flatten()).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::clip::tests::FakeClip;
+    use crate::media::tests::media_test_dir;
+
+    #[tokio::test]
+    async fn reply_carries_clip_name() {
+        let handler = VideoTapeHandler::new(Arc::new(FakeClip));
+        let dir = media_test_dir("video-tape");
+        let resp = handler
+            .run(
+                VideoTapeReq {
+                    channel: 0,
+                    pre_s: 0,
+                    duration_s: 1,
+                },
+                dir.clone(),
+            )
+            .await;
+        assert_eq!(resp.status, CmdStatus::Ok);
+        assert!(resp.file.ends_with("_ch0.h264") && !resp.file.contains('/'));
+        assert!(dir.join(&resp.file).exists());
+
+        let resp = handler
+            .run(
+                VideoTapeReq {
+                    channel: 4,
+                    pre_s: 0,
+                    duration_s: 1,
+                },
+                dir.clone(),
+            )
+            .await;
+        assert_eq!(resp.status, CmdStatus::InvalidParam);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
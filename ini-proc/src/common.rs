@@ -13,6 +13,11 @@ extern "C" fn crash(sig: i32, _: *mut libc::siginfo_t, ctx: *mut libc::c_void) {
             (*uc).uc_mcontext.arm_pc,
             (*uc).uc_mcontext.arm_lr
         );
+        #[cfg(not(target_arch = "arm"))]
+        let (pc, lr): (u64, u64) = {
+            let _ = uc;
+            (0, 0)
+        };
         
         let msg = format!("CRASH sig={} PC={:#x} LR={:#x}\n", sig, pc, lr);
         libc::write(2, msg.as_ptr() as *const _, msg.len());
@@ -26,7 +31,7 @@ extern "C" fn crash(sig: i32, _: *mut libc::siginfo_t, ctx: *mut libc::c_void) {
 pub fn setup_crash_handler() {
     unsafe {
         let mut sa: sigaction = mem::zeroed();
-        sa.sa_sigaction = crash as usize;
+        sa.sa_sigaction = crash as *const () as usize;
         libc::sigemptyset(&mut sa.sa_mask);
         sa.sa_flags = SA_SIGINFO;
 
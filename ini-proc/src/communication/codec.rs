@@ -0,0 +1,347 @@
+//! 报文编解码
+//!
+//! Wire byte order: `pack_head_flg` is sent high byte first (`AA 55`),
+//! every other multi-byte field is little-endian. Structs are packed on
+//! the wire, no padding, and only `cmd_data_len` bytes of a command's
+//! data are sent. Payloads over `CMD_DATA_MAX` go through fragment.rs
+//! before they get here.
+
+use super::protocol::{crc8, ParseErrorType, PACKAGE_HEAD_FLAG};
+use super::types::*;
+use ParseErrorType::*;
+
+pub(crate) trait WireCodec: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    fn decode(data: &[u8]) -> Result<Self, ParseErrorType>;
+}
+
+/// Bounds-checked little-endian reader over a byte slice.
+pub(crate) struct WireReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> WireReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+    pub(crate) fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], ParseErrorType> {
+        if self.remaining() < len {
+            return Err(DataTooShort);
+        }
+        let out = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(out)
+    }
+    pub(crate) fn rest(&mut self) -> &'a [u8] {
+        let out = &self.data[self.pos..];
+        self.pos = self.data.len();
+        out
+    }
+    pub(crate) fn u8(&mut self) -> Result<u8, ParseErrorType> {
+        Ok(self.bytes(1)?[0])
+    }
+    pub(crate) fn u16(&mut self) -> Result<u16, ParseErrorType> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+    pub(crate) fn u32(&mut self) -> Result<u32, ParseErrorType> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+/// Little-endian writers for building payloads.
+pub(crate) trait WireWrite {
+    fn put_u8(&mut self, v: u8);
+    fn put_u16(&mut self, v: u16);
+    fn put_u32(&mut self, v: u32);
+}
+
+impl WireWrite for Vec<u8> {
+    fn put_u8(&mut self, v: u8) {
+        self.push(v);
+    }
+    fn put_u16(&mut self, v: u16) {
+        self.extend_from_slice(&v.to_le_bytes());
+    }
+    fn put_u32(&mut self, v: u32) {
+        self.extend_from_slice(&v.to_le_bytes());
+    }
+}
+
+impl WireCodec for McuComPackageHead {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.pack_head_flg.to_be_bytes());
+        out.put_u16(self.data_len);
+        out.put_u8(self.crc);
+        out.put_u8(self.mcu_id);
+        out.put_u16(self.sn);
+        out.put_u16(self.src_sn);
+        out.put_u16(self.msg_type);
+    }
+    fn decode(data: &[u8]) -> Result<Self, ParseErrorType> {
+        let mut r = WireReader::new(data);
+        let flag = r.bytes(2)?;
+        let pack_head_flg = u16::from_be_bytes([flag[0], flag[1]]);
+        if pack_head_flg != PACKAGE_HEAD_FLAG {
+            return Err(HeadFlagError);
+        }
+        Ok(Self {
+            pack_head_flg,
+            data_len: r.u16()?,
+            crc: r.u8()?,
+            mcu_id: r.u8()?,
+            sn: r.u16()?,
+            src_sn: r.u16()?,
+            msg_type: r.u16()?,
+        })
+    }
+}
+
+impl WireCodec for CmdPackage {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.put_u16(self.cmd_type);
+        out.put_u16(self.data.len() as u16);
+        out.extend_from_slice(&self.data);
+    }
+    fn decode(data: &[u8]) -> Result<Self, ParseErrorType> {
+        let mut r = WireReader::new(data);
+        let cmd_type = r.u16()?;
+        let cmd_data_len = r.u16()?;
+        if cmd_data_len as usize != r.remaining() || r.remaining() > CMD_DATA_MAX {
+            return Err(CmdDataLenError);
+        }
+        Ok(Self::new(cmd_type, r.rest()))
+    }
+}
+
+impl WireCodec for HeartbeatPackage {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.put_u32(self.mcu_io_state);
+        out.put_u16(self.mcu_adc_value);
+        out.put_u16(self.mcu_lock_state);
+        out.put_u8(self.mcu_gps_state);
+        out.put_u8(self.mcu_gprs_state);
+        out.put_u8(self.mcu_gprs_signal);
+        out.put_u8(self.mcu_ble_state);
+        out.put_u32(self.tf_size_total);
+        out.put_u32(self.tf_size_free);
+        out.put_u32(self.remain_file);
+        out.put_u32(self.time_s);
+        out.put_u8(self.time_zone);
+        out.put_u8(self.local_record_status);
+        out.put_u8(self.gb28181_status);
+        out.put_u8(self.ai_status);
+        out.put_u8(self.alarm_status);
+        out.put_u8(self.system_status);
+        out.put_u8(self.camera_status);
+        out.put_u8(self.tf_status);
+    }
+    fn decode(data: &[u8]) -> Result<Self, ParseErrorType> {
+        if data.len() < HEARTBEAT_SIZE {
+            return Err(DataTooShort);
+        }
+        let mut r = WireReader::new(data);
+        Ok(Self {
+            mcu_io_state: r.u32()?,
+            mcu_adc_value: r.u16()?,
+            mcu_lock_state: r.u16()?,
+            mcu_gps_state: r.u8()?,
+            mcu_gprs_state: r.u8()?,
+            mcu_gprs_signal: r.u8()?,
+            mcu_ble_state: r.u8()?,
+            tf_size_total: r.u32()?,
+            tf_size_free: r.u32()?,
+            remain_file: r.u32()?,
+            time_s: r.u32()?,
+            time_zone: r.u8()?,
+            local_record_status: r.u8()?,
+            gb28181_status: r.u8()?,
+            ai_status: r.u8()?,
+            alarm_status: r.u8()?,
+            system_status: r.u8()?,
+            camera_status: r.u8()?,
+            tf_status: r.u8()?,
+        })
+    }
+}
+
+impl WireCodec for HeartbeadReplyPackage {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.put_u16(self.src_sn);
+        out.put_u16(self.reserve);
+        self.heartbeat_package.encode(out);
+    }
+    fn decode(data: &[u8]) -> Result<Self, ParseErrorType> {
+        let mut r = WireReader::new(data);
+        Ok(Self {
+            src_sn: r.u16()?,
+            reserve: r.u16()?,
+            heartbeat_package: HeartbeatPackage::decode(r.rest())?,
+        })
+    }
+}
+
+impl McuComPackage {
+    pub(crate) fn encode_payload(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match &self.data {
+            ComPackage::Cmd(cmd) => cmd.encode(&mut out),
+            ComPackage::Heartbeat(hb) => hb.encode(&mut out),
+            ComPackage::HeartbeatReply(rep) => rep.encode(&mut out),
+            ComPackage::Raw(raw) => out.extend_from_slice(raw),
+        }
+        out
+    }
+
+    /// Serializes the package, filling in `data_len` and `crc`.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let payload = self.encode_payload();
+        let head = McuComPackageHead {
+            data_len: payload.len() as u16,
+            crc: 0,
+            ..self.head
+        };
+        let mut out = Vec::with_capacity(HEAD_SIZE + payload.len());
+        head.encode(&mut out);
+        out.extend_from_slice(&payload);
+        out[CRC_OFFSET - 1] = crc8(&out[CRC_OFFSET..]);
+        out
+    }
+
+    /// Decodes the payload of an already length- and crc-checked frame.
+    pub(crate) fn decode_payload(
+        head: McuComPackageHead,
+        payload: &[u8],
+    ) -> Result<Self, ParseErrorType> {
+        let data = match McuComMsgType::try_from(head.msg_type) {
+            Ok(McuComMsgType::Cmd | McuComMsgType::CmdResp) => {
+                ComPackage::Cmd(CmdPackage::decode(payload)?)
+            }
+            Ok(McuComMsgType::HeartBeat | McuComMsgType::StateChangeHeartBeat) => {
+                ComPackage::Heartbeat(HeartbeatPackage::decode(payload)?)
+            }
+            Ok(McuComMsgType::HeartBeatRep | McuComMsgType::StateChangeHeartBeatRep) => {
+                ComPackage::HeartbeatReply(HeartbeadReplyPackage::decode(payload)?)
+            }
+            Ok(
+                McuComMsgType::OtaData
+                | McuComMsgType::OtaDataResp
+                | McuComMsgType::GpsData
+                | McuComMsgType::GpsDataResp
+                | McuComMsgType::IpcData
+                | McuComMsgType::IpcDataResp
+                | McuComMsgType::Manage
+                | McuComMsgType::ManageResp,
+            ) => {
+                ComPackage::Raw(payload.to_vec())
+            }
+            _ => return Err(HeadTypeConvError),
+        };
+        Ok(Self { head, data })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heartbeat() -> HeartbeatPackage {
+        HeartbeatPackage {
+            mcu_io_state: 0x0102_0304,
+            mcu_adc_value: 0x0506,
+            mcu_lock_state: 0x31,
+            mcu_gps_state: 1,
+            mcu_gprs_state: 2,
+            mcu_gprs_signal: 27,
+            mcu_ble_state: 1,
+            tf_size_total: 7_340_032,
+            tf_size_free: 1_048_576,
+            remain_file: 12,
+            time_s: 1_760_000_000,
+            time_zone: 8,
+            local_record_status: 1,
+            gb28181_status: 0,
+            ai_status: 0,
+            alarm_status: 0,
+            system_status: 0,
+            camera_status: 1,
+            tf_status: 0,
+        }
+    }
+
+    #[test]
+    fn head_wire_layout() {
+        let head = McuComPackageHead {
+            pack_head_flg: PACKAGE_HEAD_FLAG,
+            data_len: 0x0104,
+            crc: 0x7E,
+            mcu_id: 0x01,
+            sn: 0x1234,
+            src_sn: 0x5678,
+            msg_type: McuComMsgType::CmdResp as u16,
+        };
+        let mut out = Vec::new();
+        head.encode(&mut out);
+        assert_eq!(
+            out,
+            [0xAA, 0x55, 0x04, 0x01, 0x7E, 0x01, 0x34, 0x12, 0x78, 0x56, 0x0E, 0x00]
+        );
+        let back = McuComPackageHead::decode(&out).unwrap();
+        assert_eq!((back.sn, back.src_sn, back.data_len), (0x1234, 0x5678, 0x0104));
+    }
+
+    #[test]
+    fn heartbeat_reply_round_trip() {
+        let rep = HeartbeadReplyPackage {
+            src_sn: 9,
+            reserve: 0,
+            heartbeat_package: heartbeat(),
+        };
+        let mut out = Vec::new();
+        rep.encode(&mut out);
+        assert_eq!(out.len(), 4 + HEARTBEAT_SIZE);
+        let back = HeartbeadReplyPackage::decode(&out).unwrap();
+        assert_eq!(back.src_sn, 9);
+        assert_eq!(back.heartbeat_package.mcu_io_state, 0x0102_0304);
+        assert_eq!(back.heartbeat_package.time_s, 1_760_000_000);
+        assert_eq!(back.heartbeat_package.tf_status, 0);
+    }
+
+    #[test]
+    fn package_round_trip_fills_len_and_crc() {
+        let cmd = CmdPackage::new(CmdType::SetCarCode as u16, b"ABC");
+        let pack = McuComPackage {
+            head: McuComPackageHead {
+                pack_head_flg: PACKAGE_HEAD_FLAG,
+                data_len: 0,
+                crc: 0,
+                mcu_id: 1,
+                sn: 3,
+                src_sn: 0,
+                msg_type: McuComMsgType::Cmd as u16,
+            },
+            data: ComPackage::Cmd(cmd),
+        };
+        let bytes = pack.encode();
+        assert_eq!(bytes.len(), HEAD_SIZE + 7);
+        let head = McuComPackageHead::decode(&bytes).unwrap();
+        assert_eq!(head.data_len, 7);
+        assert_eq!(head.crc, crc8(&bytes[CRC_OFFSET..]));
+        let back = McuComPackage::decode_payload(head, &bytes[HEAD_SIZE..]).unwrap();
+        let cmd = back.as_cmd().unwrap();
+        assert_eq!(cmd.data, b"ABC");
+    }
+
+    #[test]
+    fn short_and_inconsistent_input_is_rejected() {
+        assert!(matches!(McuComPackageHead::decode(&[0xAA, 0x55, 0x00]), Err(DataTooShort)));
+        assert!(matches!(McuComPackageHead::decode(&[0x55, 0xAA, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), Err(HeadFlagError)));
+        assert!(matches!(HeartbeatPackage::decode(&[0; HEARTBEAT_SIZE - 1]), Err(DataTooShort)));
+        assert!(matches!(CmdPackage::decode(&[0x01, 0x00, 0x05, 0x00, b'a']), Err(CmdDataLenError)));
+    }
+}
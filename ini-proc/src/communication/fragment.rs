@@ -0,0 +1,239 @@
+//! 命令分片与重组
+//!
+//! A command payload longer than `CMD_DATA_MAX` is sent as several
+//! Cmd/CmdResp frames. Each fragment sets `CMD_FRAGMENT_FLAG` in
+//! `cmd_type` and starts its data with:
+//!
+//! | transfer_id u16 | index u16 | total u16 | chunk ... |
+//!
+//! The receiver rebuilds the original command under the head of
+//! fragment 0, so sn/src_sn correlation works as for a single frame.
+
+use super::codec::{WireReader, WireWrite};
+use super::types::*;
+use std::{
+    collections::HashMap,
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicU16, Ordering::Relaxed},
+    },
+    time::{Duration, Instant},
+};
+
+pub const CMD_FRAGMENT_FLAG: u16 = 0x8000;
+pub const FRAGMENT_HEADER_SIZE: usize = 6;
+pub const FRAGMENT_CHUNK_MAX: usize = CMD_DATA_MAX - FRAGMENT_HEADER_SIZE;
+pub const FRAGMENT_PAYLOAD_MAX: usize = u16::MAX as usize;
+const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(10);
+const FRAGMENT_TRANSFER_MAX: usize = 8;
+
+static TRANSFER_ID: AtomicU16 = AtomicU16::new(0);
+static REASSEMBLER: OnceLock<Mutex<Reassembler>> = OnceLock::new();
+
+/// Splits a command into frame-sized fragments. Payloads that fit one
+/// frame are returned unchanged.
+pub(crate) fn fragment_split(cmd: CmdPackage) -> Vec<CmdPackage> {
+    if cmd.data.len() <= CMD_DATA_MAX {
+        return vec![cmd];
+    }
+    let transfer_id = TRANSFER_ID.fetch_add(1, Relaxed);
+    let total = cmd.data.len().div_ceil(FRAGMENT_CHUNK_MAX) as u16;
+    cmd.data
+        .chunks(FRAGMENT_CHUNK_MAX)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut data = Vec::with_capacity(FRAGMENT_HEADER_SIZE + chunk.len());
+            data.put_u16(transfer_id);
+            data.put_u16(index as u16);
+            data.put_u16(total);
+            data.extend_from_slice(chunk);
+            CmdPackage::new(cmd.cmd_type | CMD_FRAGMENT_FLAG, &data)
+        })
+        .collect()
+}
+
+struct Transfer {
+    head: Option<McuComPackageHead>,
+    cmd_type: u16,
+    chunks: Vec<Option<Vec<u8>>>,
+    received: usize,
+    started: Instant,
+}
+
+pub(crate) struct Reassembler {
+    transfers: HashMap<(u8, u16), Transfer>,
+    timeout: Duration,
+}
+
+impl Reassembler {
+    pub(crate) fn new(timeout: Duration) -> Self {
+        Self {
+            transfers: HashMap::new(),
+            timeout,
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn in_progress(&self) -> usize {
+        self.transfers.len()
+    }
+
+    /// Drops transfers that have not completed within the timeout.
+    pub(crate) fn cleanup(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        self.transfers.retain(|(mcu_id, id), t| {
+            let alive = now.duration_since(t.started) < timeout;
+            if !alive {
+                println!(
+                    "fragment transfer {}:{} timeout, {}/{} received",
+                    mcu_id,
+                    id,
+                    t.received,
+                    t.chunks.len()
+                );
+            }
+            alive
+        });
+    }
+
+    /// Feeds a package in. Non-fragments pass straight through; a
+    /// fragment returns the rebuilt package once its transfer completes.
+    pub(crate) fn push(&mut self, pack: McuComPackage, now: Instant) -> Option<McuComPackage> {
+        let cmd = match pack.as_cmd() {
+            Some(cmd) if cmd.cmd_type & CMD_FRAGMENT_FLAG != 0 => cmd,
+            _ => return Some(pack),
+        };
+        self.cleanup(now);
+
+        let mut r = WireReader::new(&cmd.data);
+        let (Ok(transfer_id), Ok(index), Ok(total)) = (r.u16(), r.u16(), r.u16()) else {
+            println!("fragment header too short");
+            return None;
+        };
+        let cmd_type = cmd.cmd_type & !CMD_FRAGMENT_FLAG;
+        if total == 0
+            || index >= total
+            || total as usize > FRAGMENT_PAYLOAD_MAX.div_ceil(FRAGMENT_CHUNK_MAX)
+        {
+            println!("bad fragment {}/{}", index, total);
+            return None;
+        }
+        let key = (pack.head.mcu_id, transfer_id);
+        if !self.transfers.contains_key(&key) && self.transfers.len() >= FRAGMENT_TRANSFER_MAX {
+            println!("too many fragment transfers, drop {}", transfer_id);
+            return None;
+        }
+        let transfer = self.transfers.entry(key).or_insert_with(|| Transfer {
+            head: None,
+            cmd_type,
+            chunks: vec![None; total as usize],
+            received: 0,
+            started: now,
+        });
+        if transfer.chunks.len() != total as usize || transfer.cmd_type != cmd_type {
+            println!("fragment {} does not match transfer {}", index, transfer_id);
+            return None;
+        }
+        let slot = &mut transfer.chunks[index as usize];
+        if slot.is_none() {
+            *slot = Some(r.rest().to_vec());
+            transfer.received += 1;
+        }
+        if index == 0 {
+            transfer.head = Some(pack.head);
+        }
+        if transfer.received < transfer.chunks.len() {
+            return None;
+        }
+
+        let transfer = self.transfers.remove(&key)?;
+        let data: Vec<u8> = transfer.chunks.into_iter().flatten().flatten().collect();
+        if data.len() > FRAGMENT_PAYLOAD_MAX {
+            println!("fragment transfer {} too long: {}", transfer_id, data.len());
+            return None;
+        }
+        let mut head = transfer.head?;
+        head.data_len = (CMD_HEADER_SIZE as usize + data.len()).min(u16::MAX as usize) as u16;
+        Some(McuComPackage {
+            head,
+            data: ComPackage::Cmd(CmdPackage::new(transfer.cmd_type, &data)),
+        })
+    }
+}
+
+pub(crate) fn fragment_reassemble(pack: McuComPackage) -> Option<McuComPackage> {
+    REASSEMBLER
+        .get_or_init(|| Mutex::new(Reassembler::new(FRAGMENT_TIMEOUT)))
+        .lock()
+        .ok()?
+        .push(pack, Instant::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communication::protocol::PACKAGE_HEAD_FLAG;
+
+    fn wrap(sn: u16, cmd: CmdPackage) -> McuComPackage {
+        McuComPackage {
+            head: McuComPackageHead {
+                pack_head_flg: PACKAGE_HEAD_FLAG,
+                data_len: 0,
+                crc: 0,
+                mcu_id: 2,
+                sn,
+                src_sn: 40,
+                msg_type: McuComMsgType::CmdResp as u16,
+            },
+            data: ComPackage::Cmd(cmd),
+        }
+    }
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn small_payload_is_not_fragmented() {
+        let cmd = CmdPackage::new(CmdType::VersionResp as u16, &payload(CMD_DATA_MAX));
+        assert_eq!(fragment_split(cmd.clone()), vec![cmd]);
+    }
+
+    #[test]
+    fn out_of_order_fragments_are_rebuilt() {
+        let data = payload(1100);
+        let mut frags = fragment_split(CmdPackage::new(CmdType::VersionResp as u16, &data));
+        assert_eq!(frags.len(), 5);
+        assert!(frags.iter().all(|f| f.data.len() <= CMD_DATA_MAX));
+        frags.swap(0, 3);
+
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(FRAGMENT_TIMEOUT);
+        let mut done = None;
+        for (i, frag) in frags.into_iter().enumerate() {
+            let first = u16::from_le_bytes([frag.data[2], frag.data[3]]) == 0;
+            let sn = if first { 100 } else { 200 + i as u16 };
+            done = reassembler.push(wrap(sn, frag), now);
+        }
+        let pack = done.expect("transfer not completed");
+        assert_eq!(pack.head.sn, 100);
+        assert_eq!(pack.head.src_sn, 40);
+        let cmd = pack.as_cmd().unwrap();
+        assert_eq!(cmd.cmd_type, CmdType::VersionResp as u16);
+        assert_eq!(cmd.data, data);
+        assert_eq!(reassembler.in_progress(), 0);
+    }
+
+    #[test]
+    fn incomplete_transfer_expires() {
+        let frags = fragment_split(CmdPackage::new(CmdType::OtaResp as u16, &payload(600)));
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(Duration::from_secs(1));
+        assert!(reassembler.push(wrap(1, frags[0].clone()), now).is_none());
+        assert_eq!(reassembler.in_progress(), 1);
+        reassembler.cleanup(now + Duration::from_secs(2));
+        assert_eq!(reassembler.in_progress(), 0);
+        // 过期后的迟到分片只会开启一个新的不完整传输
+        assert!(reassembler.push(wrap(2, frags[1].clone()), now).is_none());
+    }
+}
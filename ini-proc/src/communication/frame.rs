@@ -0,0 +1,148 @@
+use super::protocol::*;
+use super::types::*;
+
+/// pack_head_flg 在线路上的字节序列
+const HEAD_FLAG_BYTES: [u8; 2] = PACKAGE_HEAD_FLAG.to_be_bytes();
+
+/// Stateful decoder for the TCP byte stream.
+///
+/// Keeps partial frames between reads, resyncs on the head flag after
+/// garbage or a broken frame, and yields every complete package.
+pub(crate) struct FrameDecoder {
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub(crate) fn new() -> Self {
+        Self {
+            buf: Vec::with_capacity(2 * FRAME_MAX_SIZE),
+        }
+    }
+
+    pub(crate) fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    pub(crate) fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub(crate) fn next_package(&mut self) -> Option<McuComPackage> {
+        loop {
+            if !self.resync() {
+                return None;
+            }
+            match parse_package_head(&self.buf) {
+                ParseResult::NeedMore => return None,
+                ParseResult::Success(pack) => {
+                    self.buf.drain(..HEAD_SIZE + pack.head.data_len as usize);
+                    return Some(pack);
+                }
+                // crc 通过但内容不支持，整帧丢弃
+                ParseResult::Error(err @ (ParseErrorType::HeadTypeConvError
+                | ParseErrorType::CmdDataLenError
+                | ParseErrorType::DataTooShort)) => {
+                    println!("drop frame: {:?}", err);
+                    let data_len = u16::from_le_bytes([self.buf[2], self.buf[3]]) as usize;
+                    self.buf.drain(..HEAD_SIZE + data_len);
+                }
+                // 帧头不可信，跳过当前标志重新同步
+                ParseResult::Error(err) => {
+                    println!("resync after: {:?}", err);
+                    self.buf.drain(..1);
+                }
+            }
+        }
+    }
+
+    /// Drops bytes until the buffer starts with the head flag.
+    /// Returns false when no complete flag is buffered yet.
+    fn resync(&mut self) -> bool {
+        match self
+            .buf
+            .windows(HEAD_FLAG_BYTES.len())
+            .position(|w| w == HEAD_FLAG_BYTES)
+        {
+            Some(0) => true,
+            Some(pos) => {
+                println!("skip {} garbage bytes", pos);
+                self.buf.drain(..pos);
+                true
+            }
+            None => {
+                // 末尾可能是半个标志，保留
+                let keep = usize::from(self.buf.last() == Some(&HEAD_FLAG_BYTES[0]));
+                let skip = self.buf.len() - keep;
+                self.buf.drain(..skip);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(sn: u16, msg_type: McuComMsgType, payload: &[u8]) -> Vec<u8> {
+        let mut buf = HEAD_FLAG_BYTES.to_vec();
+        buf.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        buf.push(0);
+        buf.push(0x02);
+        buf.extend_from_slice(&sn.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&u16::from(msg_type).to_le_bytes());
+        buf.extend_from_slice(payload);
+        buf[CRC_OFFSET - 1] = crc8(&buf[CRC_OFFSET..]);
+        buf
+    }
+
+    fn cmd_frame(sn: u16, data: &[u8]) -> Vec<u8> {
+        let mut payload = (CmdType::SetIp as u16).to_le_bytes().to_vec();
+        payload.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        payload.extend_from_slice(data);
+        frame(sn, McuComMsgType::Cmd, &payload)
+    }
+
+    fn drain(decoder: &mut FrameDecoder) -> Vec<u16> {
+        std::iter::from_fn(|| decoder.next_package())
+            .map(|p| p.head.sn)
+            .collect()
+    }
+
+    #[test]
+    fn split_frame_is_buffered() {
+        let bytes = cmd_frame(1, b"192.168.1.2");
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&bytes[..7]);
+        assert!(decoder.next_package().is_none());
+        decoder.push(&bytes[7..]);
+        assert_eq!(drain(&mut decoder), [1]);
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn back_to_back_frames() {
+        let mut bytes = frame(1, McuComMsgType::HeartBeat, &[0; 36]);
+        bytes.extend(cmd_frame(2, b"x"));
+        bytes.extend(frame(3, McuComMsgType::HeartBeat, &[0; 36]));
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&bytes);
+        assert_eq!(drain(&mut decoder), [1, 2, 3]);
+    }
+
+    #[test]
+    fn resync_after_garbage_and_bad_crc() {
+        let mut bad = cmd_frame(1, b"abc");
+        let last = bad.len() - 1;
+        bad[last] ^= 0xFF;
+        let mut bytes = vec![0x00, 0xAA, 0x13, 0x55];
+        bytes.extend(bad);
+        bytes.extend(cmd_frame(2, b"abc"));
+        let mut decoder = FrameDecoder::new();
+        for chunk in bytes.chunks(5) {
+            decoder.push(chunk);
+        }
+        assert_eq!(drain(&mut decoder), [2]);
+    }
+}
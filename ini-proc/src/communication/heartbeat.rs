@@ -0,0 +1,56 @@
+use super::types::*;
+use crate::config::ini_parse;
+use crate::storage::emmc::{emmc_get_info, emmc_get_remainfile_count};
+use std::{path::Path, time::SystemTime};
+
+const DEFAULT_TIME_ZONE: i8 = 8;
+
+fn ini_is_on(section: &str, key: &str) -> u8 {
+    u8::from(ini_parse::ini_get_ini_config(section, key).is_some_and(|v| v == "on"))
+}
+
+/// 时区按小时偏移，负数以补码放进 u8
+pub(crate) fn heartbeat_time_zone() -> u8 {
+    ini_parse::ini_get_ini_config("system", "time_zone")
+        .and_then(|v| v.parse::<i8>().ok())
+        .unwrap_or(DEFAULT_TIME_ZONE) as u8
+}
+
+/// Builds the status we report back, echoing the MCU's own fields from
+/// `peer`. Storage figures are in KB. Blocking: walks the events dir.
+pub(crate) fn heartbeat_local_status(peer: Option<&HeartbeatPackage>) -> HeartbeatPackage {
+    let (tf_size_total, tf_size_free, tf_ok) = match emmc_get_info() {
+        Some(info) if info.mount_status() => (
+            info.total_size().min(u32::MAX as u64) as u32,
+            info.free_size().min(u32::MAX as u64) as u32,
+            !info.is_read_only(),
+        ),
+        _ => (0, 0, false),
+    };
+    let remain_file = emmc_get_remainfile_count().unwrap_or(0).min(u32::MAX as u64) as u32;
+    let time_s = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs().min(u32::MAX as u64) as u32);
+
+    HeartbeatPackage {
+        mcu_io_state: peer.map_or(0, |p| p.mcu_io_state),
+        mcu_adc_value: peer.map_or(0, |p| p.mcu_adc_value),
+        mcu_lock_state: peer.map_or(0, |p| p.mcu_lock_state),
+        mcu_gps_state: peer.map_or(0, |p| p.mcu_gps_state),
+        mcu_gprs_state: peer.map_or(0, |p| p.mcu_gprs_state),
+        mcu_gprs_signal: peer.map_or(0, |p| p.mcu_gprs_signal),
+        mcu_ble_state: peer.map_or(0, |p| p.mcu_ble_state),
+        tf_size_total,
+        tf_size_free,
+        remain_file,
+        time_s,
+        time_zone: heartbeat_time_zone(),
+        local_record_status: ini_is_on("system", "recorder"),
+        gb28181_status: ini_is_on("gb28181", "status"),
+        ai_status: ini_is_on("system", "yolov5s"),
+        alarm_status: 0,
+        system_status: 0,
+        camera_status: u8::from(!Path::new("/dev/video0").exists()),
+        tf_status: u8::from(!tf_ok),
+    }
+}
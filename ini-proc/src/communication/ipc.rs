@@ -0,0 +1,298 @@
+//! IPC 数据通道
+//!
+//! IpcData carries application-defined payloads between ini-proc and the
+//! MCU without taking a new CmdType for each integration (e.g. the event
+//! media listing in `media::list`):
+//!
+//! | sub_type u16 | tag u8 | len u16 | value ... | tag u8 | ... |
+//!
+//! Incoming messages are routed by `sub_type` to a handler registered
+//! with `ipc_register`; its result is acked with IpcDataResp
+//! `| sub_type u16 | status u8 | tlv ... |`. Unregistered sub-types are
+//! acked with `IpcStatus::Unsupported`, replies too long for one frame
+//! with `IpcStatus::Failed`. Outbound messages go through `ipc_send`,
+//! which waits for the MCU's IpcDataResp. Payloads are not fragmented and
+//! must fit one frame.
+
+use super::codec::{WireReader, WireWrite};
+use super::pending::RequestError;
+use super::protocol::*;
+use super::reliable::reliable_request;
+use super::types::*;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use std::{
+    collections::HashMap,
+    sync::{OnceLock, RwLock},
+};
+use tokio::sync::mpsc;
+
+const IPC_HEAD_SIZE: usize = 2;
+const IPC_RESP_HEAD_SIZE: usize = 3;
+static IPC_HANDLERS: OnceLock<RwLock<HashMap<u16, IpcHandler>>> = OnceLock::new();
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive)]
+pub enum IpcStatus {
+    Ok = 0,
+    Unsupported = 1,
+    InvalidData = 2,
+    Failed = 3,
+}
+
+#[derive(Debug)]
+pub enum IpcError {
+    TooLong(usize),
+    Request(RequestError),
+    BadReply,
+    Status(IpcStatus),
+}
+
+impl std::fmt::Display for IpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpcError::TooLong(len) => write!(f, "message too long: {}", len),
+            IpcError::Request(err) => write!(f, "request failed: {:?}", err),
+            IpcError::BadReply => f.write_str("bad reply"),
+            IpcError::Status(status) => write!(f, "status {:?}", status),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tlv {
+    pub tag: u8,
+    pub value: Vec<u8>,
+}
+
+impl Tlv {
+    pub fn new(tag: u8, value: &[u8]) -> Self {
+        Self {
+            tag,
+            value: value.to_vec(),
+        }
+    }
+    pub fn from_u32(tag: u8, v: u32) -> Self {
+        Self::new(tag, &v.to_le_bytes())
+    }
+    pub fn from_str(tag: u8, v: &str) -> Self {
+        Self::new(tag, v.as_bytes())
+    }
+    pub fn as_u32(&self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.value.as_slice().try_into().ok()?))
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.value).ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IpcMessage {
+    pub sub_type: u16,
+    pub items: Vec<Tlv>,
+}
+
+impl IpcMessage {
+    pub fn new(sub_type: u16, items: Vec<Tlv>) -> Self {
+        Self { sub_type, items }
+    }
+
+    pub fn get(&self, tag: u8) -> Option<&Tlv> {
+        self.items.iter().find(|t| t.tag == tag)
+    }
+
+    fn decode_items(r: &mut WireReader) -> Option<Vec<Tlv>> {
+        let mut items = Vec::new();
+        while r.remaining() > 0 {
+            let tag = r.u8().ok()?;
+            let len = r.u16().ok()? as usize;
+            items.push(Tlv::new(tag, r.bytes(len).ok()?));
+        }
+        Some(items)
+    }
+
+    fn encode_items(&self, out: &mut Vec<u8>) {
+        for item in &self.items {
+            out.put_u8(item.tag);
+            out.put_u16(item.value.len() as u16);
+            out.extend_from_slice(&item.value);
+        }
+    }
+
+    pub(crate) fn decode(data: &[u8]) -> Option<Self> {
+        let mut r = WireReader::new(data);
+        let sub_type = r.u16().ok()?;
+        Some(Self::new(sub_type, Self::decode_items(&mut r)?))
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(IPC_HEAD_SIZE);
+        out.put_u16(self.sub_type);
+        self.encode_items(&mut out);
+        out
+    }
+
+    /// 应答: sub_type, status, tlv
+    pub(crate) fn decode_reply(data: &[u8]) -> Option<(IpcStatus, Self)> {
+        let mut r = WireReader::new(data);
+        let sub_type = r.u16().ok()?;
+        let status = IpcStatus::try_from(r.u8().ok()?).ok()?;
+        Some((status, Self::new(sub_type, Self::decode_items(&mut r)?)))
+    }
+
+    pub(crate) fn encode_reply(&self, status: IpcStatus) -> Vec<u8> {
+        let mut out = Vec::with_capacity(IPC_RESP_HEAD_SIZE);
+        out.put_u16(self.sub_type);
+        out.put_u8(status.into());
+        self.encode_items(&mut out);
+        out
+    }
+}
+
+/// Handlers run on the connection task and must not block. The returned
+/// items are sent back in the IpcDataResp.
+pub type IpcHandler = Box<dyn Fn(&IpcMessage) -> Result<Vec<Tlv>, IpcStatus> + Send + Sync>;
+
+fn ipc_handlers() -> &'static RwLock<HashMap<u16, IpcHandler>> {
+    IPC_HANDLERS.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// Registers the handler for `sub_type`, replacing any previous one.
+pub fn ipc_register(sub_type: u16, handler: IpcHandler) -> Option<i32> {
+    if ipc_handlers().write().ok()?.insert(sub_type, handler).is_some() {
+        println!("ipc handler for sub_type {} replaced", sub_type);
+    }
+    Some(0)
+}
+
+fn ipc_handle(msg: &IpcMessage) -> (IpcStatus, Vec<Tlv>) {
+    let Ok(handlers) = ipc_handlers().read() else {
+        return (IpcStatus::Failed, Vec::new());
+    };
+    match handlers.get(&msg.sub_type).map(|h| h(msg)) {
+        Some(Ok(items)) => (IpcStatus::Ok, items),
+        Some(Err(status)) => (status, Vec::new()),
+        None => {
+            println!("ipc sub_type {} not registered", msg.sub_type);
+            (IpcStatus::Unsupported, Vec::new())
+        }
+    }
+}
+
+pub(crate) async fn ipc_process(pack: McuComPackage, tx: &mpsc::Sender<Vec<u8>>) {
+    let Some(raw) = pack.as_raw() else {
+        return;
+    };
+    let reply = match IpcMessage::decode(raw) {
+        Some(msg) => {
+            let (status, items) = ipc_handle(&msg);
+            IpcMessage::new(msg.sub_type, items).encode_reply(status)
+        }
+        None => {
+            let sub_type = WireReader::new(raw).u16().unwrap_or(0);
+            IpcMessage::new(sub_type, Vec::new()).encode_reply(IpcStatus::InvalidData)
+        }
+    };
+    let reply = if reply.len() > COM_PACKAGE_MAX_SIZE {
+        println!("ipc reply too long: {}", reply.len());
+        let sub_type = WireReader::new(raw).u16().unwrap_or(0);
+        IpcMessage::new(sub_type, Vec::new()).encode_reply(IpcStatus::Failed)
+    } else {
+        reply
+    };
+    protocol_package_send(ComPackage::Raw(reply), McuComMsgType::IpcDataResp, Some(&pack.head), tx).await;
+}
+
+/// Sends `msg` to the MCU and returns the items of its ack.
+pub async fn ipc_send(msg: &IpcMessage, tx: &mpsc::Sender<Vec<u8>>) -> Result<IpcMessage, IpcError> {
+    let data = msg.encode();
+    if data.len() > COM_PACKAGE_MAX_SIZE {
+        return Err(IpcError::TooLong(data.len()));
+    }
+    let resp = reliable_request(ComPackage::Raw(data), McuComMsgType::IpcData, tx)
+        .await
+        .map_err(IpcError::Request)?;
+    let (status, reply) = resp
+        .as_raw()
+        .and_then(IpcMessage::decode_reply)
+        .ok_or(IpcError::BadReply)?;
+    match status {
+        IpcStatus::Ok => Ok(reply),
+        status => Err(IpcError::Status(status)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(sub_type: u16, items: Vec<Tlv>) -> McuComPackage {
+        McuComPackage {
+            head: McuComPackageHead {
+                pack_head_flg: PACKAGE_HEAD_FLAG,
+                data_len: 0,
+                crc: 0,
+                mcu_id: 2,
+                sn: 500 + sub_type,
+                src_sn: 0,
+                msg_type: McuComMsgType::IpcData as u16,
+            },
+            data: ComPackage::Raw(IpcMessage::new(sub_type, items).encode()),
+        }
+    }
+
+    async fn roundtrip(pack: McuComPackage) -> (IpcStatus, IpcMessage) {
+        let (tx, mut rx) = mpsc::channel(8);
+        let ParseResult::Success(pack) = parse_package_head(&pack.encode()) else {
+            panic!("bad frame");
+        };
+        protocol_dispatch(pack, &tx).await;
+        let ParseResult::Success(reply) = parse_package_head(&rx.recv().await.unwrap()) else {
+            panic!("bad frame");
+        };
+        assert_eq!(reply.head.msg_type, McuComMsgType::IpcDataResp as u16);
+        IpcMessage::decode_reply(reply.as_raw().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn tlv_round_trips() {
+        let msg = IpcMessage::new(
+            0x0102,
+            vec![Tlv::from_u32(1, 0xDEAD_BEEF), Tlv::from_str(2, "hello"), Tlv::new(3, &[])],
+        );
+        let back = IpcMessage::decode(&msg.encode()).unwrap();
+        assert_eq!(back, msg);
+        assert_eq!(back.get(1).unwrap().as_u32(), Some(0xDEAD_BEEF));
+        assert_eq!(back.get(2).unwrap().as_str(), Some("hello"));
+        // 截断的 TLV
+        let data = msg.encode();
+        assert!(IpcMessage::decode(&data[..data.len() - 3 - 2]).is_none());
+    }
+
+    #[tokio::test]
+    async fn registered_handler_is_acked() {
+        ipc_register(
+            0x7001,
+            Box::new(|msg| {
+                let v = msg.get(1).and_then(Tlv::as_u32).ok_or(IpcStatus::InvalidData)?;
+                Ok(vec![Tlv::from_u32(1, v + 1)])
+            }),
+        );
+        let (status, reply) = roundtrip(request(0x7001, vec![Tlv::from_u32(1, 41)])).await;
+        assert_eq!(status, IpcStatus::Ok);
+        assert_eq!(reply.sub_type, 0x7001);
+        assert_eq!(reply.get(1).unwrap().as_u32(), Some(42));
+
+        let (status, _) = roundtrip(request(0x7001, vec![])).await;
+        assert_eq!(status, IpcStatus::InvalidData);
+        let (status, _) = roundtrip(request(0x7002, vec![])).await;
+        assert_eq!(status, IpcStatus::Unsupported);
+    }
+
+    #[tokio::test]
+    async fn oversized_reply_is_acked_failed() {
+        ipc_register(0x7003, Box::new(|_| Ok(vec![Tlv::new(1, &[0; COM_PACKAGE_MAX_SIZE])])));
+        let (status, reply) = roundtrip(request(0x7003, vec![])).await;
+        assert_eq!(status, IpcStatus::Failed);
+        assert_eq!(reply, IpcMessage::new(0x7003, vec![]));
+    }
+}
@@ -0,0 +1,206 @@
+//! 远程配置管理
+//!
+//! Manage messages read and change the INI held by `ini_parse`:
+//!
+//! | op u8 | str8 ... |        str8 = | len u8 | utf8 ... |
+//!
+//! - Get: section, key -> value
+//! - ListSections: -> count u8, str8 ...
+//! - ListKeys: section -> count u8, str8 ...
+//! - Set: section, key, value -> value (in memory only; `gb28181` keys
+//!   are saved at once so the client re-registers with them)
+//! - Save: persist all changes to the INI file
+//!
+//! The global section is addressed with an empty name. Passwords can be
+//! set but are never returned, Get and Set answer them masked; setting
+//! the mask itself keeps the stored password. Every
+//! request is answered with ManageResp `| op u8 | status u8 | body ... |`.
+
+use super::codec::{WireReader, WireWrite};
+use super::protocol::*;
+use super::types::*;
+use crate::config::ini_parse::*;
+use crate::config::validate::{ConfigError, ini_mask_secret};
+use crate::system::gb28181::{GB28181_SECTION, gb28181_configure};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use tokio::sync::mpsc;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive)]
+pub enum ManageOp {
+    Get = 1,
+    ListSections = 2,
+    ListKeys = 3,
+    Set = 4,
+    Save = 5,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive)]
+pub enum ManageStatus {
+    Ok = 0,
+    InvalidRequest = 1,
+    UnknownOp = 2,
+    NotFound = 3,
+    InvalidValue = 4,
+    ReadOnly = 5,
+    PersistFailed = 6,
+    TooLong = 7,
+    Failed = 8,
+}
+
+impl From<ConfigError> for ManageStatus {
+    fn from(err: ConfigError) -> Self {
+        match err {
+            ConfigError::NotInitialized => ManageStatus::Failed,
+            ConfigError::NotFound => ManageStatus::NotFound,
+            ConfigError::ReadOnly => ManageStatus::ReadOnly,
+            ConfigError::InvalidValue => ManageStatus::InvalidValue,
+            ConfigError::PersistFailed => ManageStatus::PersistFailed,
+        }
+    }
+}
+
+fn read_str8(r: &mut WireReader) -> Result<String, ManageStatus> {
+    let len = r.u8().map_err(|_| ManageStatus::InvalidRequest)?;
+    let bytes = r.bytes(len as usize).map_err(|_| ManageStatus::InvalidRequest)?;
+    String::from_utf8(bytes.to_vec()).map_err(|_| ManageStatus::InvalidRequest)
+}
+
+fn put_str8(out: &mut Vec<u8>, s: &str) -> Result<(), ManageStatus> {
+    let len = u8::try_from(s.len()).map_err(|_| ManageStatus::TooLong)?;
+    out.put_u8(len);
+    out.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn put_list(out: &mut Vec<u8>, items: &[String]) -> Result<(), ManageStatus> {
+    out.put_u8(u8::try_from(items.len()).map_err(|_| ManageStatus::TooLong)?);
+    for item in items {
+        put_str8(out, item)?;
+    }
+    Ok(())
+}
+
+/// Executes one request and returns the reply body.
+fn manage_handle(op: ManageOp, r: &mut WireReader) -> Result<Vec<u8>, ManageStatus> {
+    let mut body = Vec::new();
+    match op {
+        ManageOp::Get => {
+            let (section, key) = (read_str8(r)?, read_str8(r)?);
+            let value = ini_get_ini_config_in(&section, &key).ok_or(ManageStatus::NotFound)?;
+            put_str8(&mut body, ini_mask_secret(Some(&section), &key, &value))?;
+        }
+        ManageOp::ListSections => {
+            put_list(&mut body, &ini_get_sections().ok_or(ManageStatus::Failed)?)?;
+        }
+        ManageOp::ListKeys => {
+            let section = read_str8(r)?;
+            put_list(&mut body, &ini_get_section_keys(&section).ok_or(ManageStatus::NotFound)?)?;
+        }
+        ManageOp::Set => {
+            let (section, key, value) = (read_str8(r)?, read_str8(r)?, read_str8(r)?);
+            if section == GB28181_SECTION {
+                gb28181_configure(&[(key.clone(), value.clone())])?;
+            } else {
+                ini_set_ini_config(&section, &key, &value)?;
+            }
+            let value = ini_mask_secret(Some(&section), &key, &value);
+            println!("manage set [{}] {}={}", section, key, value);
+            put_str8(&mut body, value)?;
+        }
+        ManageOp::Save => ini_save_config()?,
+    }
+    Ok(body)
+}
+
+pub(crate) fn manage_reply(raw: &[u8]) -> Vec<u8> {
+    let mut r = WireReader::new(raw);
+    let op = r.u8().unwrap_or(0);
+    let ret = ManageOp::try_from(op)
+        .map_err(|_| ManageStatus::UnknownOp)
+        .and_then(|op| manage_handle(op, &mut r));
+    let mut out = vec![op];
+    match ret {
+        Ok(body) if body.len() + 2 <= COM_PACKAGE_MAX_SIZE => {
+            out.put_u8(ManageStatus::Ok.into());
+            out.extend_from_slice(&body);
+        }
+        Ok(_) => out.put_u8(ManageStatus::TooLong.into()),
+        Err(status) => out.put_u8(status.into()),
+    }
+    out
+}
+
+pub(crate) async fn manage_process(pack: McuComPackage, tx: &mpsc::Sender<Vec<u8>>) {
+    let Some(raw) = pack.as_raw() else {
+        return;
+    };
+    let raw = raw.to_vec();
+    // Save 会写文件
+    let reply = match tokio::task::spawn_blocking(move || manage_reply(&raw)).await {
+        Ok(reply) => reply,
+        Err(err) => {
+            println!("manage request failed: {}", err);
+            return;
+        }
+    };
+    protocol_package_send(ComPackage::Raw(reply), McuComMsgType::ManageResp, Some(&pack.head), tx).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(op: ManageOp, args: &[&str]) -> Vec<u8> {
+        let mut out = vec![op.into()];
+        for arg in args {
+            put_str8(&mut out, arg).unwrap();
+        }
+        out
+    }
+
+    fn status(reply: &[u8]) -> ManageStatus {
+        ManageStatus::try_from(reply[1]).unwrap()
+    }
+
+    #[test]
+    fn get_set_and_save() {
+        let path = ini_test_init();
+
+        let reply = manage_reply(&request(ManageOp::Get, &["system", "time_zone"]));
+        assert_eq!(status(&reply), ManageStatus::Ok);
+        let reply = manage_reply(&request(ManageOp::ListSections, &[]));
+        assert_eq!(status(&reply), ManageStatus::Ok);
+        assert!(reply.windows(7).any(|w| w == b"gb28181"));
+
+        let reply = manage_reply(&request(ManageOp::Set, &["quectel", "apn", "cmnet"]));
+        assert_eq!(status(&reply), ManageStatus::Ok);
+        let reply = manage_reply(&request(ManageOp::Get, &["quectel", "apn"]));
+        assert_eq!(&reply[2..], b"\x05cmnet");
+        let reply = manage_reply(&request(ManageOp::Set, &["gb28181", "alertId", "7"]));
+        assert_eq!(status(&reply), ManageStatus::Ok);
+        assert!(std::fs::read_to_string(&path).unwrap().contains("alertId=7"));
+        let reply = manage_reply(&request(ManageOp::Get, &["network", "ftp_pwd"]));
+        assert_eq!(&reply[2..], b"\x06******");
+        // 掩码写回不覆盖原密码
+        let ftp_pwd = ini_get_ini_config("network", "ftp_pwd");
+        let reply = manage_reply(&request(ManageOp::Set, &["network", "ftp_pwd", "******"]));
+        assert_eq!(status(&reply), ManageStatus::Ok);
+        assert_eq!(ini_get_ini_config("network", "ftp_pwd"), ftp_pwd);
+        assert_ne!(ftp_pwd.as_deref(), Some("******"));
+        let reply = manage_reply(&request(ManageOp::Set, &["network", "tcp_server_ip", "1.2.3"]));
+        assert_eq!(status(&reply), ManageStatus::InvalidValue);
+        let reply = manage_reply(&request(ManageOp::Set, &["network", "no_such_key", "1"]));
+        assert_eq!(status(&reply), ManageStatus::NotFound);
+        let reply = manage_reply(&request(ManageOp::Set, &["", "soc", "x"]));
+        assert_eq!(status(&reply), ManageStatus::ReadOnly);
+
+        let reply = manage_reply(&request(ManageOp::Save, &[]));
+        assert_eq!(status(&reply), ManageStatus::Ok);
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(saved.contains("apn=cmnet"));
+        assert_eq!(status(&manage_reply(&[9])), ManageStatus::UnknownOp);
+        assert_eq!(status(&manage_reply(&[1, 6, b's'])), ManageStatus::InvalidRequest);
+    }
+}
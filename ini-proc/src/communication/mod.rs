@@ -1,3 +1,12 @@
+pub mod codec;
+pub mod fragment;
+pub mod frame;
+pub mod heartbeat;
+pub mod ipc;
+pub mod manage;
+pub mod peer_state;
+pub mod pending;
 pub mod protocol;
+pub mod reliable;
 pub mod types;
 pub mod tcp_transport;
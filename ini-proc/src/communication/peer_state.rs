@@ -0,0 +1,292 @@
+//! MCU 侧状态
+//!
+//! Every heartbeat from the MCU updates the latest known state, appends
+//! to a bounded history, and broadcasts what changed so other subsystems
+//! can react (e.g. the container lock being opened). The history can be
+//! read back by the MCU over IPC sub-type `PEER_IPC_HISTORY`: optional
+//! `since u32` unix seconds (tag 1); the reply has one sample (tag 1)
+//! `| time_s u32 | mcu_id u8 | lock u16 | locked u8 | io u32 |` per
+//! heartbeat, oldest first, as many of the newest as fit one frame.
+
+use super::codec::WireWrite;
+use super::ipc::{IpcMessage, IpcStatus, Tlv, ipc_register};
+use super::types::*;
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, OnceLock},
+    time::SystemTime,
+};
+use tokio::sync::broadcast;
+
+const PEER_HISTORY_MAX: usize = 120;
+const PEER_EVENT_CAPACITY: usize = 32;
+pub const PEER_IPC_HISTORY: u16 = 0x0201;
+const TAG_SINCE: u8 = 1;
+const TAG_SAMPLE: u8 = 1;
+const PEER_SAMPLE_SIZE: usize = 12;
+/// sub_type + status
+const REPLY_FIXED_SIZE: usize = 3;
+static PEER_STATE: OnceLock<Mutex<PeerStateStore>> = OnceLock::new();
+static PEER_EVENTS: OnceLock<broadcast::Sender<PeerEvent>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy)]
+pub struct PeerSample {
+    pub at: SystemTime,
+    pub mcu_id: u8,
+    pub state: HeartbeatPackage,
+}
+
+impl PeerSample {
+    pub fn is_locked(&self) -> bool {
+        lock_is_locked(self.state.mcu_lock_state)
+    }
+
+    pub fn time_s(&self) -> u32 {
+        self.at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as u32)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(PEER_SAMPLE_SIZE);
+        out.put_u32(self.time_s());
+        out.put_u8(self.mcu_id);
+        out.put_u16(self.state.mcu_lock_state);
+        out.put_u8(self.is_locked() as u8);
+        out.put_u32(self.state.mcu_io_state);
+        out
+    }
+}
+
+/// 施封/上锁状态: 1 或 0x31('1') 表示上锁
+pub fn lock_is_locked(lock_state: u16) -> bool {
+    matches!(lock_state, 1 | 0x31)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PeerEvent {
+    Lock { old: u16, new: u16 },
+    Io { old: u32, new: u32, changed: u32 },
+    GpsState { old: u8, new: u8 },
+    GprsState { old: u8, new: u8 },
+    BleState { old: u8, new: u8 },
+}
+
+impl PeerEvent {
+    pub fn is_lock_opened(&self) -> bool {
+        matches!(self, PeerEvent::Lock { old, new } if lock_is_locked(*old) && !lock_is_locked(*new))
+    }
+}
+
+pub(crate) struct PeerStateStore {
+    history: VecDeque<PeerSample>,
+    capacity: usize,
+}
+
+impl PeerStateStore {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            history: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub(crate) fn latest(&self) -> Option<&PeerSample> {
+        self.history.back()
+    }
+
+    /// Stores a new sample and returns what changed since the last one.
+    /// The first sample only sets the baseline.
+    pub(crate) fn update(&mut self, sample: PeerSample) -> Vec<PeerEvent> {
+        let mut events = Vec::new();
+        if let Some(prev) = self.latest().map(|p| p.state) {
+            let new = &sample.state;
+            if prev.mcu_lock_state != new.mcu_lock_state {
+                events.push(PeerEvent::Lock {
+                    old: prev.mcu_lock_state,
+                    new: new.mcu_lock_state,
+                });
+            }
+            if prev.mcu_io_state != new.mcu_io_state {
+                events.push(PeerEvent::Io {
+                    old: prev.mcu_io_state,
+                    new: new.mcu_io_state,
+                    changed: prev.mcu_io_state ^ new.mcu_io_state,
+                });
+            }
+            if prev.mcu_gps_state != new.mcu_gps_state {
+                events.push(PeerEvent::GpsState {
+                    old: prev.mcu_gps_state,
+                    new: new.mcu_gps_state,
+                });
+            }
+            if prev.mcu_gprs_state != new.mcu_gprs_state {
+                events.push(PeerEvent::GprsState {
+                    old: prev.mcu_gprs_state,
+                    new: new.mcu_gprs_state,
+                });
+            }
+            if prev.mcu_ble_state != new.mcu_ble_state {
+                events.push(PeerEvent::BleState {
+                    old: prev.mcu_ble_state,
+                    new: new.mcu_ble_state,
+                });
+            }
+        }
+        if self.history.len() >= self.capacity {
+            self.history.pop_front();
+        }
+        self.history.push_back(sample);
+        events
+    }
+}
+
+fn peer_events() -> &'static broadcast::Sender<PeerEvent> {
+    PEER_EVENTS.get_or_init(|| broadcast::channel(PEER_EVENT_CAPACITY).0)
+}
+
+fn peer_store() -> &'static Mutex<PeerStateStore> {
+    PEER_STATE.get_or_init(|| Mutex::new(PeerStateStore::new(PEER_HISTORY_MAX)))
+}
+
+/// Records a heartbeat from `mcu_id` and broadcasts the resulting events.
+pub(crate) fn peer_state_update(mcu_id: u8, state: &HeartbeatPackage) -> Vec<PeerEvent> {
+    let sample = PeerSample {
+        at: SystemTime::now(),
+        mcu_id,
+        state: *state,
+    };
+    let events = match peer_store().lock() {
+        Ok(mut store) => store.update(sample),
+        Err(_) => return Vec::new(),
+    };
+    for event in &events {
+        println!("peer state event: {:?}", event);
+        // 没有订阅者时发送失败，忽略
+        let _ = peer_events().send(*event);
+    }
+    events
+}
+
+pub fn peer_state_latest() -> Option<PeerSample> {
+    peer_store().lock().ok()?.latest().copied()
+}
+
+pub fn peer_state_history() -> Vec<PeerSample> {
+    peer_store()
+        .lock()
+        .map(|s| s.history.iter().copied().collect())
+        .unwrap_or_default()
+}
+
+pub fn peer_state_subscribe() -> broadcast::Receiver<PeerEvent> {
+    peer_events().subscribe()
+}
+
+pub(crate) fn peer_history_reply(history: &[PeerSample], since: u32) -> Vec<Tlv> {
+    let fit = (COM_PACKAGE_MAX_SIZE - REPLY_FIXED_SIZE) / (3 + PEER_SAMPLE_SIZE);
+    let mut items: Vec<Tlv> = history
+        .iter()
+        .rev()
+        .filter(|s| s.time_s() >= since)
+        .take(fit)
+        .map(|s| Tlv::new(TAG_SAMPLE, &s.encode()))
+        .collect();
+    items.reverse();
+    items
+}
+
+fn peer_history_handle(msg: &IpcMessage) -> Result<Vec<Tlv>, IpcStatus> {
+    let since = match msg.get(TAG_SINCE) {
+        Some(tlv) => tlv.as_u32().ok_or(IpcStatus::InvalidData)?,
+        None => 0,
+    };
+    Ok(peer_history_reply(&peer_state_history(), since))
+}
+
+pub fn peer_state_ipc_init() -> Option<i32> {
+    ipc_register(PEER_IPC_HISTORY, Box::new(peer_history_handle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(io: u32, lock: u16) -> PeerSample {
+        PeerSample {
+            at: SystemTime::now(),
+            mcu_id: 2,
+            state: HeartbeatPackage {
+                mcu_io_state: io,
+                mcu_adc_value: 0,
+                mcu_lock_state: lock,
+                mcu_gps_state: 1,
+                mcu_gprs_state: 1,
+                mcu_gprs_signal: 20,
+                mcu_ble_state: 0,
+                tf_size_total: 0,
+                tf_size_free: 0,
+                remain_file: 0,
+                time_s: 0,
+                time_zone: 8,
+                local_record_status: 0,
+                gb28181_status: 0,
+                ai_status: 0,
+                alarm_status: 0,
+                system_status: 0,
+                camera_status: 0,
+                tf_status: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn changes_are_reported_against_previous_sample() {
+        let mut store = PeerStateStore::new(4);
+        assert!(store.update(sample(0b0101, 0x31)).is_empty());
+        assert!(store.update(sample(0b0101, 0x31)).is_empty());
+
+        let events = store.update(sample(0b0110, 0x30));
+        assert_eq!(
+            events,
+            [
+                PeerEvent::Lock { old: 0x31, new: 0x30 },
+                PeerEvent::Io {
+                    old: 0b0101,
+                    new: 0b0110,
+                    changed: 0b0011
+                },
+            ]
+        );
+        assert!(events[0].is_lock_opened());
+        assert!(!store.latest().unwrap().is_locked());
+    }
+
+    #[test]
+    fn history_reply_keeps_newest_that_fit() {
+        let mut history: Vec<PeerSample> = (0..200).map(|io| sample(io, 0x31)).collect();
+        let old = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000);
+        history[0].at = old;
+        let items = peer_history_reply(&history, 0);
+        assert!(items.len() < 200);
+        assert!(REPLY_FIXED_SIZE + items.len() * (3 + PEER_SAMPLE_SIZE) <= COM_PACKAGE_MAX_SIZE);
+        let last = &items.last().unwrap().value;
+        assert_eq!(last.len(), PEER_SAMPLE_SIZE);
+        assert_eq!(last[4], 2);
+        assert_eq!(last[7], 1);
+        assert_eq!(u32::from_le_bytes(last[8..12].try_into().unwrap()), 199);
+
+        let items = peer_history_reply(&history[..3], 2_000);
+        assert_eq!(items.len(), 2);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut store = PeerStateStore::new(3);
+        for io in 0..5 {
+            store.update(sample(io, 1));
+        }
+        let io: Vec<u32> = store.history.iter().map(|s| s.state.mcu_io_state).collect();
+        assert_eq!(io, [2, 3, 4]);
+    }
+}
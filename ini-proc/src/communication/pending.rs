@@ -0,0 +1,50 @@
+use super::types::*;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+use tokio::sync::oneshot;
+
+/// 等待应答的请求表，以请求的 sn 为键
+static PENDING: OnceLock<Mutex<HashMap<u16, oneshot::Sender<McuComPackage>>>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum RequestError {
+    SendFailed,
+    Timeout,
+    Cancelled,
+}
+
+fn pending_table() -> &'static Mutex<HashMap<u16, oneshot::Sender<McuComPackage>>> {
+    PENDING.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub(crate) fn pending_register(sn: u16) -> oneshot::Receiver<McuComPackage> {
+    let (tx, rx) = oneshot::channel();
+    if let Ok(mut table) = pending_table().lock()
+        && table.insert(sn, tx).is_some()
+    {
+        println!("pending sn {} replaced", sn);
+    }
+    rx
+}
+
+pub(crate) fn pending_cancel(sn: u16) {
+    if let Ok(mut table) = pending_table().lock() {
+        table.remove(&sn);
+    }
+}
+
+pub(crate) fn pending_count() -> usize {
+    pending_table().lock().map(|t| t.len()).unwrap_or(0)
+}
+
+/// Hands a response to the request waiting on its `src_sn`.
+/// Gives the package back when nobody is waiting for it.
+pub(crate) fn pending_complete(pack: McuComPackage) -> Option<McuComPackage> {
+    let waiter = pending_table().lock().ok()?.remove(&pack.head.src_sn);
+    match waiter {
+        Some(waiter) => waiter.send(pack).err(),
+        None => Some(pack),
+    }
+}
@@ -1,23 +1,44 @@
+use super::codec::WireCodec;
+use super::fragment::*;
+use super::heartbeat::heartbeat_local_status;
+use super::ipc::ipc_process;
+use super::manage::manage_process;
+use super::pending::*;
+use super::peer_state::peer_state_update;
+use super::reliable::*;
 use super::types::*;
+use crate::gps::fix::gps_process;
+use crate::command::registry::cmd_dispatch;
+use crate::ota::firmware::ota_process;
 
 use ParseErrorType::*;
 use ParseResult::*;
-use std::sync::atomic::{AtomicU16, Ordering::Relaxed};
+use std::{
+    sync::atomic::{AtomicU16, Ordering::Relaxed},
+    time::Duration,
+};
 use tokio::sync::mpsc;
 const CMD_TYPE: u16 = McuComMsgType::Cmd as u16;
 const HEARTBEAT_TYPE: u16 = McuComMsgType::HeartBeat as u16;
-const HEARTBEAT_RESP_TYPE: u16 = McuComMsgType::HeartBeatRep as u16;
+const OTA_DATA_TYPE: u16 = McuComMsgType::OtaData as u16;
+const STATE_CHANGE_TYPE: u16 = McuComMsgType::StateChangeHeartBeat as u16;
+const GPS_DATA_TYPE: u16 = McuComMsgType::GpsData as u16;
+const IPC_DATA_TYPE: u16 = McuComMsgType::IpcData as u16;
+const MANAGE_TYPE: u16 = McuComMsgType::Manage as u16;
 const MUC_ID: u8 = 0x01;
-const PACKAGE_HEAD_FLAG: u16 = 0xAA55;
+/// 协议版本，报文格式有不兼容的改动时加一
+pub const PROTOCOL_VERSION: u16 = 1;
+pub(crate) const PACKAGE_HEAD_FLAG: u16 = 0xAA55;
+pub(crate) const FRAME_MAX_SIZE: usize = HEAD_SIZE + COM_PACKAGE_MAX_SIZE;
 static LOCAL_SN: AtomicU16 = AtomicU16::new(0);
 #[derive(Debug)]
 pub(crate) enum ParseErrorType {
     DataTooShort,
+    HeadFlagError,
     HeadTypeConvError,
     MsgDataTooLong,
     CmdDataLenError,
     CrcVerifyError,
-    NoneError,
 }
 pub enum ParseResult {
     NeedMore,
@@ -38,19 +59,39 @@ pub(crate) fn crc8(data: &[u8]) -> u8 {
     }
     crc
 }
-pub(crate) async fn protocol_parse(data: &[u8], tx: &mpsc::Sender<Vec<u8>>) -> i32 {
-    let pack = match parse_package_head(data).await {
-        Success(pack) => pack,
-        NeedMore => return 1,
-        Error(err) => {
-            println!("parse_package_head error: {:?}", err);
-            return -1;
+pub(crate) async fn protocol_dispatch(pack: McuComPackage, tx: &mpsc::Sender<Vec<u8>>) -> i32 {
+    println!("package msg type: {}", pack.head.msg_type);
+    let Some(pack) = fragment_reassemble(pack) else {
+        return 0;
+    };
+    let is_response = McuComMsgType::try_from(pack.head.msg_type).is_ok_and(|t| t.is_response());
+    let pack = if is_response && pack.head.src_sn != 0 {
+        match pending_complete(pack) {
+            None => return 0,
+            Some(pack) => {
+                println!("no pending request for src_sn: {}", pack.head.src_sn);
+                pack
+            }
         }
+    } else {
+        pack
     };
-    println!("package msg type: {}", pack.head.msg_type);
+    if !is_response && let Delivery::Duplicate(replies) = reliable_check(&pack.head) {
+        println!("duplicate request sn: {}, re-ack {} replies", pack.head.sn, replies.len());
+        for reply in replies {
+            if tx.send(reply).await.is_err() {
+                break;
+            }
+        }
+        return 0;
+    }
     match pack.head.msg_type {
-        HEARTBEAT_TYPE => process_heartbeat(pack).await,
+        HEARTBEAT_TYPE | STATE_CHANGE_TYPE => process_heartbeat(pack, tx).await,
         CMD_TYPE => process_cmd(pack, tx).await,
+        OTA_DATA_TYPE => ota_process(pack, tx).await,
+        GPS_DATA_TYPE => gps_process(pack, tx).await,
+        IPC_DATA_TYPE => ipc_process(pack, tx).await,
+        MANAGE_TYPE => manage_process(pack, tx).await,
         _ => {
             println!("unknown msg_type: {}", pack.head.msg_type);
         }
@@ -59,135 +100,310 @@ pub(crate) async fn protocol_parse(data: &[u8], tx: &mpsc::Sender<Vec<u8>>) -> i
 }
 
 pub(crate) async fn process_cmd(pack: McuComPackage, tx: &mpsc::Sender<Vec<u8>>) {
-    quick_reply(tx).await;
-    if let Some(cmd) = pack.as_cmd() {
-        match CmdType::try_from(cmd.cmd_type) {
-            Ok(CmdType::RemoteCapture) => (),
-            Ok(CmdType::SetIp) => {
-                quick_reply(tx).await;
-            }
-            Ok(CmdType::SetCoordinate) => {
-                common_respond(CmdType::SetCoordinate,1,tx).await;
-            }
-            Err(err) => {
-                println!("cmdtype trabs err: {}", err);
-            }
-            Ok(CmdType::Unknown) => {
-                println!("cmd_type: {}", cmd.cmd_type);
-            }
-            _ => {
-                println!(
-                    "Exists but has not been realized cmd_type: {}",
-                    cmd.cmd_type
-                );
-            }
-        }
-    }
+    cmd_dispatch(&pack, tx).await;
 }
 
-pub(crate) async fn process_heartbeat(pack: McuComPackage) {}
-pub(crate) async fn parse_package_head(data: &[u8]) -> ParseResult {
-    if data.len() < HEAD_SIZE {
-        return NeedMore;
+/// 周期心跳和状态变化心跳都更新对端状态，并以对应的应答类型回复
+pub(crate) async fn process_heartbeat(pack: McuComPackage, tx: &mpsc::Sender<Vec<u8>>) {
+    let reply_type = match McuComMsgType::try_from(pack.head.msg_type).ok().and_then(|t| t.response_type()) {
+        Some(t) => t,
+        None => return,
+    };
+    let peer = pack.as_heartbeat().copied();
+    if let Some(peer) = &peer {
+        peer_state_update(pack.head.mcu_id, peer);
     }
-    let pack: McuComPackage = McuComPackage::bytes_to_struct(data);
-    if data.len() < HEAD_SIZE + pack.head.data_len as usize {
+    let status = match tokio::task::spawn_blocking(move || heartbeat_local_status(peer.as_ref())).await {
+        Ok(status) => status,
+        Err(err) => {
+            println!("heartbeat status collect failed: {}", err);
+            return;
+        }
+    };
+    let reply = HeartbeadReplyPackage {
+        src_sn: pack.head.sn,
+        reserve: 0,
+        heartbeat_package: status,
+    };
+    let data = ComPackage::HeartbeatReply(reply);
+    protocol_package_send(data, reply_type, Some(&pack.head), tx).await;
+}
+pub(crate) fn parse_package_head(data: &[u8]) -> ParseResult {
+    if data.len() < HEAD_SIZE {
         return NeedMore;
     }
-    if pack.head.data_len as usize > std::mem::size_of::<ComPackage>() {
+    let head = match McuComPackageHead::decode(data) {
+        Ok(head) => head,
+        Err(err) => return Error(err),
+    };
+    if head.data_len as usize > COM_PACKAGE_MAX_SIZE {
         println!("data_len too long");
-        return Error(CmdDataLenError);
+        return Error(MsgDataTooLong);
+    }
+    if data.len() < HEAD_SIZE + head.data_len as usize {
+        return NeedMore;
     }
 
-    let crc_end = pack.head.data_len as usize + HEAD_SIZE;
-    if crc8(&data[CRC_OFFSET..crc_end]) != pack.head.crc {
+    let crc_end = head.data_len as usize + HEAD_SIZE;
+    if crc8(&data[CRC_OFFSET..crc_end]) != head.crc {
         println!("cmd crc8 verify error");
         return Error(CrcVerifyError);
     }
 
-    match pack.head.msg_type {
-        CMD_TYPE => {
-            if let Some(cmd) = pack.as_cmd() {
-                let expected = pack.head.data_len - CMD_HEADER_SIZE;
-                if cmd.cmd_data_len != expected {
-                    return Error(CmdDataLenError);
-                }
-            }
+    println!("parse_package_head: {:#?}", head);
+    match McuComPackage::decode_payload(head, &data[HEAD_SIZE..crc_end]) {
+        Ok(pack) => Success(pack),
+        Err(err) => Error(err),
+    }
+}
+// sn 0 保留给 src_sn 表示"非应答"
+pub(crate) fn protocol_next_sn() -> u16 {
+    loop {
+        let sn = LOCAL_SN.fetch_add(1, Relaxed).wrapping_add(1);
+        if sn != 0 {
+            return sn;
         }
-        HEARTBEAT_TYPE | HEARTBEAT_RESP_TYPE => {}
-        _ => return Error(HeadTypeConvError),
     }
-    println!("parse_package_head: {:#?}", pack.head);
-    Success(pack)
 }
+/// Sends a package. `req` is the request being answered, its sn is
+/// echoed in `src_sn`. Returns the sn used when the frames were queued.
 pub(crate) async fn protocol_package_send(
-    head_data: ComPackage,
+    data: ComPackage,
     msg_type: McuComMsgType,
-    size: u16,
+    req: Option<&McuComPackageHead>,
     tx: &mpsc::Sender<Vec<u8>>,
-) {
-    let pack_len = HEAD_SIZE + size as usize;
-    LOCAL_SN.store(LOCAL_SN.load(Relaxed) + 1, Relaxed);
-    let mut pack = McuComPackage {
-        head: McuComPackageHead {
-            pack_head_flg: PACKAGE_HEAD_FLAG.swap_bytes(),
-            data_len: size,
-            crc: 0,
-            mcu_id: MUC_ID,
-            sn: LOCAL_SN.load(Relaxed),
-            src_sn: 0,
-            msg_type: msg_type as u16,
-        },
-        data: head_data,
-    };
-    let buf = McuComPackage::struct_to_bytes(&pack);
-    pack.head.crc = crc8(&buf[CRC_OFFSET..pack_len]);
-    let _ = tx.try_send(McuComPackage::struct_to_bytes(&pack));
+) -> Option<u16> {
+    let sn = protocol_next_sn();
+    let frames = protocol_package_build(data, msg_type, sn, req.map_or(0, |r| r.sn));
+    if let Some(req) = req {
+        reliable_record_reply(req, &frames);
+    }
+    for frame in frames {
+        tx.send(frame).await.ok()?;
+    }
+    Some(sn)
 }
-pub(crate) async fn quick_reply(tx: &mpsc::Sender<Vec<u8>>) {
-    let mut cmd_pack = CmdPackage {
-        cmd_type: 0x6666,
-        cmd_data_len: 0,
-        data: [0; 256],
+/// Encodes a package into frames, fragmenting oversized commands.
+/// The first frame carries `sn`, later fragments take fresh ones.
+pub(crate) fn protocol_package_build(
+    data: ComPackage,
+    msg_type: McuComMsgType,
+    sn: u16,
+    src_sn: u16,
+) -> Vec<Vec<u8>> {
+    let parts = match data {
+        ComPackage::Cmd(cmd) => fragment_split(cmd).into_iter().map(ComPackage::Cmd).collect(),
+        other => vec![other],
     };
-    let data = b"0";
-    let len = data.len().min(cmd_pack.data.len());
-    cmd_pack.data[..len].copy_from_slice(&data[..len]);
-    cmd_pack.cmd_data_len = len as u16;
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, data)| {
+            let pack = McuComPackage {
+                head: McuComPackageHead {
+                    pack_head_flg: PACKAGE_HEAD_FLAG,
+                    data_len: 0,
+                    crc: 0,
+                    mcu_id: MUC_ID,
+                    sn: if i == 0 { sn } else { protocol_next_sn() },
+                    src_sn,
+                    msg_type: msg_type as u16,
+                },
+                data,
+            };
+            pack.encode()
+        })
+        .collect()
+}
+/// Sends a request and waits for the response echoing its sn.
+pub(crate) async fn protocol_request(
+    data: ComPackage,
+    msg_type: McuComMsgType,
+    timeout: Duration,
+    tx: &mpsc::Sender<Vec<u8>>,
+) -> Result<McuComPackage, RequestError> {
+    protocol_request_sn(data, msg_type, protocol_next_sn(), timeout, tx).await
+}
+pub(crate) async fn protocol_request_sn(
+    data: ComPackage,
+    msg_type: McuComMsgType,
+    sn: u16,
+    timeout: Duration,
+    tx: &mpsc::Sender<Vec<u8>>,
+) -> Result<McuComPackage, RequestError> {
+    let rx = pending_register(sn);
+    for frame in protocol_package_build(data, msg_type, sn, 0) {
+        if tx.send(frame).await.is_err() {
+            pending_cancel(sn);
+            return Err(RequestError::SendFailed);
+        }
+    }
+    match tokio::time::timeout(timeout, rx).await {
+        Ok(Ok(pack)) => Ok(pack),
+        Ok(Err(_)) => Err(RequestError::Cancelled),
+        Err(_) => {
+            pending_cancel(sn);
+            println!("request sn {} timeout, {} still pending", sn, pending_count());
+            Err(RequestError::Timeout)
+        }
+    }
+}
+#[cfg(test)]
+pub(crate) async fn common_respond(
+    cmdtype: CmdType,
+    status: crate::command::payload::CmdStatus,
+    req: &McuComPackageHead,
+    tx: &mpsc::Sender<Vec<u8>>,
+) {
+    use crate::command::payload::{CmdPayload, StatusResp};
+    let cmd_pack = CmdPackage::new(cmdtype as u16, &StatusResp { status }.encode());
 
-    let com_pack = ComPackage {
-        cmd_package: cmd_pack, // 初始化 union 的一个字段
-    };
-    protocol_package_send(
-        com_pack,
-        McuComMsgType::CmdResp,
-        cmd_pack.cmd_data_len + 4,
-        tx,
-    )
-    .await;
-    // let _ = tx.try_send(McuComPackage::struct_to_bytes(&cmd_pack));
+    protocol_package_send(ComPackage::Cmd(cmd_pack), McuComMsgType::CmdResp, Some(req), tx).await;
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::payload::CmdStatus;
 
-pub(crate) async fn common_respond(cmdtype:CmdType,state: u16, tx: &mpsc::Sender<Vec<u8>>){
-    let mut cmd_pack = CmdPackage {
-        cmd_type: cmdtype as u16,
-        cmd_data_len: 0,
-        data: [0; 256],
-    };
-    let data = format!("{},{:04?}",state,333);
-    let data = data.as_bytes();
-    let len = data.len().min(cmd_pack.data.len());
-    cmd_pack.data[..len].copy_from_slice(&data[..len]);
-    cmd_pack.cmd_data_len = len as u16;
+    fn decode(frame: &[u8]) -> McuComPackage {
+        match parse_package_head(frame) {
+            Success(pack) => pack,
+            _ => panic!("bad frame"),
+        }
+    }
 
-    let com_pack = ComPackage {
-        cmd_package: cmd_pack, // 初始化 union 的一个字段
-    };
-    protocol_package_send(
-        com_pack,
-        McuComMsgType::CmdResp,
-        cmd_pack.cmd_data_len + 4,
-        tx,
-    )
-    .await;
-}
\ No newline at end of file
+    fn cmd(cmd_type: CmdType) -> ComPackage {
+        ComPackage::Cmd(CmdPackage::new(cmd_type as u16, &[]))
+    }
+
+    #[tokio::test]
+    async fn responses_match_requests_by_sn() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let timeout = Duration::from_secs(1);
+        let first = tokio::spawn({
+            let tx = tx.clone();
+            async move { protocol_request(cmd(CmdType::Version), McuComMsgType::Cmd, timeout, &tx).await }
+        });
+        let req1 = decode(&rx.recv().await.unwrap());
+        let second = tokio::spawn({
+            let tx = tx.clone();
+            async move { protocol_request(cmd(CmdType::SetTime), McuComMsgType::Cmd, timeout, &tx).await }
+        });
+        let req2 = decode(&rx.recv().await.unwrap());
+
+        // 乱序应答
+        let (peer_tx, mut peer_rx) = mpsc::channel(8);
+        common_respond(CmdType::SetTimeResp, CmdStatus::Ok, &req2.head, &peer_tx).await;
+        common_respond(CmdType::VersionResp, CmdStatus::Ok, &req1.head, &peer_tx).await;
+        for _ in 0..2 {
+            let resp = decode(&peer_rx.recv().await.unwrap());
+            assert_eq!(protocol_dispatch(resp, &tx).await, 0);
+        }
+
+        let resp1 = first.await.unwrap().unwrap();
+        let resp2 = second.await.unwrap().unwrap();
+        assert_eq!(resp1.head.src_sn, req1.head.sn);
+        assert_eq!(resp1.as_cmd().unwrap().cmd_type, CmdType::VersionResp as u16);
+        assert_eq!(resp2.head.src_sn, req2.head.sn);
+        assert_eq!(resp2.as_cmd().unwrap().cmd_type, CmdType::SetTimeResp as u16);
+    }
+
+    #[tokio::test]
+    async fn heartbeat_is_answered_with_local_status() {
+        let hb = HeartbeatPackage {
+            mcu_io_state: 0x11,
+            mcu_adc_value: 3300,
+            mcu_lock_state: 0x31,
+            mcu_gps_state: 1,
+            mcu_gprs_state: 1,
+            mcu_gprs_signal: 20,
+            mcu_ble_state: 0,
+            tf_size_total: 0,
+            tf_size_free: 0,
+            remain_file: 0,
+            time_s: 0,
+            time_zone: 0,
+            local_record_status: 0,
+            gb28181_status: 0,
+            ai_status: 0,
+            alarm_status: 0,
+            system_status: 0,
+            camera_status: 0,
+            tf_status: 0,
+        };
+        let pack = McuComPackage {
+            head: McuComPackageHead {
+                pack_head_flg: PACKAGE_HEAD_FLAG,
+                data_len: 0,
+                crc: 0,
+                mcu_id: 2,
+                sn: 77,
+                src_sn: 0,
+                msg_type: McuComMsgType::HeartBeat as u16,
+            },
+            data: ComPackage::Heartbeat(hb),
+        };
+        let (tx, mut rx) = mpsc::channel(8);
+        protocol_dispatch(pack, &tx).await;
+        let reply = decode(&rx.recv().await.unwrap());
+        assert_eq!(reply.head.msg_type, McuComMsgType::HeartBeatRep as u16);
+        assert_eq!(reply.head.src_sn, 77);
+        let rep = reply.as_heartbeat_reply().unwrap();
+        assert_eq!(rep.src_sn, 77);
+        assert_eq!(rep.heartbeat_package.mcu_lock_state, 0x31);
+        assert!(rep.heartbeat_package.time_s > 1_700_000_000);
+        assert_eq!(rep.heartbeat_package.time_zone, 8);
+        // 测试环境没有挂载 eMMC
+        assert_eq!(rep.heartbeat_package.tf_status, 1);
+    }
+
+    #[tokio::test]
+    async fn state_change_heartbeat_is_parsed_and_acked() {
+        let mut hb = heartbeat_local_status(None);
+        hb.mcu_lock_state = 0x30;
+        let pack = McuComPackage {
+            head: McuComPackageHead {
+                pack_head_flg: PACKAGE_HEAD_FLAG,
+                data_len: 0,
+                crc: 0,
+                mcu_id: 3,
+                sn: 91,
+                src_sn: 0,
+                msg_type: McuComMsgType::StateChangeHeartBeat as u16,
+            },
+            data: ComPackage::Heartbeat(hb),
+        };
+        let pack = decode(&pack.encode());
+        assert_eq!(pack.as_heartbeat().unwrap().mcu_lock_state, 0x30);
+
+        let (tx, mut rx) = mpsc::channel(8);
+        protocol_dispatch(pack, &tx).await;
+        let reply = decode(&rx.recv().await.unwrap());
+        assert_eq!(reply.head.msg_type, McuComMsgType::StateChangeHeartBeatRep as u16);
+        assert_eq!(reply.head.src_sn, 91);
+        assert_eq!(reply.as_heartbeat_reply().unwrap().heartbeat_package.mcu_lock_state, 0x30);
+    }
+
+    #[tokio::test]
+    async fn unanswered_request_times_out() {
+        let (tx, _rx) = mpsc::channel(8);
+        let sn = protocol_next_sn();
+        let timeout = Duration::from_millis(20);
+        let ret = protocol_request_sn(cmd(CmdType::Version), McuComMsgType::Cmd, sn, timeout, &tx).await;
+        assert!(matches!(ret, Err(RequestError::Timeout)));
+        // 超时后迟到的应答不再有等待者
+        let late = McuComPackage {
+            head: McuComPackageHead {
+                pack_head_flg: PACKAGE_HEAD_FLAG,
+                data_len: 0,
+                crc: 0,
+                mcu_id: 2,
+                sn: 1,
+                src_sn: sn,
+                msg_type: McuComMsgType::CmdResp as u16,
+            },
+            data: cmd(CmdType::VersionResp),
+        };
+        assert!(pending_complete(late).is_some());
+    }
+}
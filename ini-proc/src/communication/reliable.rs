@@ -0,0 +1,304 @@
+//! 可靠传输层（可选）
+//!
+//! Requests we send are retransmitted with the same sn until the matching
+//! response arrives. Requests we receive are checked against a window of
+//! recently seen sns per peer (`mcu_id`); a duplicate is answered with the
+//! replies cached for the original instead of being executed again. A
+//! reply is cached as all the frames it was sent in, so a fragmented one
+//! is replayed whole.
+//! Nothing here is active until `reliable_init` is called.
+
+use super::pending::RequestError;
+use super::protocol::*;
+use super::types::*;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+/// 每个请求只缓存首条应答(受理)和最新一条(进度或最终应答)，每条含全部分片
+const REPLY_CACHE_MAX: usize = 2;
+static RELIABLE: OnceLock<Mutex<ReliableState>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RetransmitPolicy {
+    pub retries: u8,
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ReliableConfig {
+    pub default_policy: RetransmitPolicy,
+    pub policies: HashMap<McuComMsgType, RetransmitPolicy>,
+    pub window: usize,
+}
+
+impl Default for ReliableConfig {
+    fn default() -> Self {
+        let mut policies = HashMap::new();
+        // 心跳周期性发送，丢了等下一次即可
+        policies.insert(
+            McuComMsgType::HeartBeat,
+            RetransmitPolicy {
+                retries: 0,
+                timeout: DEFAULT_TIMEOUT,
+            },
+        );
+        policies.insert(
+            McuComMsgType::OtaData,
+            RetransmitPolicy {
+                retries: 5,
+                timeout: Duration::from_secs(5),
+            },
+        );
+        Self {
+            default_policy: RetransmitPolicy {
+                retries: 3,
+                timeout: DEFAULT_TIMEOUT,
+            },
+            policies,
+            window: 32,
+        }
+    }
+}
+
+pub(crate) enum Delivery {
+    New,
+    // 重复请求，附带原请求已发出的应答帧
+    Duplicate(Vec<Vec<u8>>),
+}
+
+struct SeenRequest {
+    sn: u16,
+    // 每条应答的全部帧
+    replies: Vec<Vec<Vec<u8>>>,
+}
+
+pub(crate) struct ReliableState {
+    config: ReliableConfig,
+    seen: HashMap<u8, VecDeque<SeenRequest>>,
+}
+
+impl ReliableState {
+    pub(crate) fn new(config: ReliableConfig) -> Self {
+        Self {
+            config,
+            seen: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn policy(&self, msg_type: McuComMsgType) -> RetransmitPolicy {
+        *self
+            .config
+            .policies
+            .get(&msg_type)
+            .unwrap_or(&self.config.default_policy)
+    }
+
+    /// Records an incoming request, or reports it as a duplicate.
+    pub(crate) fn check(&mut self, head: &McuComPackageHead) -> Delivery {
+        let window = self.seen.entry(head.mcu_id).or_default();
+        if let Some(seen) = window.iter().find(|s| s.sn == head.sn) {
+            return Delivery::Duplicate(seen.replies.concat());
+        }
+        if window.len() >= self.config.window {
+            window.pop_front();
+        }
+        window.push_back(SeenRequest {
+            sn: head.sn,
+            replies: Vec::new(),
+        });
+        Delivery::New
+    }
+
+    pub(crate) fn record_reply(&mut self, req: &McuComPackageHead, frames: &[Vec<u8>]) {
+        if let Some(seen) = self
+            .seen
+            .get_mut(&req.mcu_id)
+            .and_then(|w| w.iter_mut().rev().find(|s| s.sn == req.sn))
+        {
+            if seen.replies.len() >= REPLY_CACHE_MAX {
+                seen.replies.pop();
+            }
+            seen.replies.push(frames.to_vec());
+        }
+    }
+}
+
+pub(crate) fn reliable_init(config: ReliableConfig) -> Option<i32> {
+    RELIABLE.get_or_init(|| {
+        println!("reliable layer init ok, window: {}", config.window);
+        Mutex::new(ReliableState::new(config))
+    });
+    Some(0)
+}
+
+pub(crate) fn reliable_check(head: &McuComPackageHead) -> Delivery {
+    match RELIABLE.get().and_then(|r| r.lock().ok()) {
+        Some(mut state) => state.check(head),
+        None => Delivery::New,
+    }
+}
+
+pub(crate) fn reliable_record_reply(req: &McuComPackageHead, frames: &[Vec<u8>]) {
+    if let Some(mut state) = RELIABLE.get().and_then(|r| r.lock().ok()) {
+        state.record_reply(req, frames);
+    }
+}
+
+pub(crate) fn reliable_policy(msg_type: McuComMsgType) -> RetransmitPolicy {
+    match RELIABLE.get().and_then(|r| r.lock().ok()) {
+        Some(state) => state.policy(msg_type),
+        None => RetransmitPolicy {
+            retries: 0,
+            timeout: DEFAULT_TIMEOUT,
+        },
+    }
+}
+
+/// Sends a request, retransmitting it per the policy of its type.
+pub(crate) async fn reliable_request(
+    data: ComPackage,
+    msg_type: McuComMsgType,
+    tx: &mpsc::Sender<Vec<u8>>,
+) -> Result<McuComPackage, RequestError> {
+    reliable_request_with(data, msg_type, reliable_policy(msg_type), tx).await
+}
+
+pub(crate) async fn reliable_request_with(
+    data: ComPackage,
+    msg_type: McuComMsgType,
+    policy: RetransmitPolicy,
+    tx: &mpsc::Sender<Vec<u8>>,
+) -> Result<McuComPackage, RequestError> {
+    if policy.retries == 0 {
+        return protocol_request(data, msg_type, policy.timeout, tx).await;
+    }
+    let sn = protocol_next_sn();
+    let mut ret = Err(RequestError::Timeout);
+    for attempt in 0..=policy.retries {
+        if attempt > 0 {
+            println!("retransmit sn {} attempt {}", sn, attempt);
+        }
+        ret = protocol_request_sn(data.clone(), msg_type, sn, policy.timeout, tx).await;
+        if !matches!(ret, Err(RequestError::Timeout)) {
+            break;
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communication::fragment::fragment_reassemble;
+
+    fn head(mcu_id: u8, sn: u16) -> McuComPackageHead {
+        McuComPackageHead {
+            pack_head_flg: PACKAGE_HEAD_FLAG,
+            data_len: 0,
+            crc: 0,
+            mcu_id,
+            sn,
+            src_sn: 0,
+            msg_type: McuComMsgType::Cmd as u16,
+        }
+    }
+
+    #[test]
+    fn duplicate_is_reacked_with_cached_reply() {
+        let mut state = ReliableState::new(ReliableConfig::default());
+        assert!(matches!(state.check(&head(2, 7)), Delivery::New));
+        state.record_reply(&head(2, 7), &[vec![1, 2, 3]]);
+        match state.check(&head(2, 7)) {
+            Delivery::Duplicate(replies) => assert_eq!(replies, vec![vec![1, 2, 3]]),
+            Delivery::New => panic!("duplicate not detected"),
+        }
+        // 不同的对端各自独立
+        assert!(matches!(state.check(&head(3, 7)), Delivery::New));
+    }
+
+    #[test]
+    fn reply_cache_keeps_first_and_latest() {
+        let mut state = ReliableState::new(ReliableConfig::default());
+        state.check(&head(2, 9));
+        for frame in 1..=5u8 {
+            state.record_reply(&head(2, 9), &[vec![frame]]);
+        }
+        match state.check(&head(2, 9)) {
+            Delivery::Duplicate(replies) => assert_eq!(replies, vec![vec![1], vec![5]]),
+            Delivery::New => panic!("duplicate not detected"),
+        }
+    }
+
+    #[test]
+    fn fragmented_reply_is_replayed_whole() {
+        let mut state = ReliableState::new(ReliableConfig::default());
+        let req = head(2, 11);
+        state.check(&req);
+        let data = vec![0x5a; CMD_DATA_MAX * 2 + 10];
+        let reply = ComPackage::Cmd(CmdPackage::new(CmdType::VersionResp as u16, &data));
+        let frames = protocol_package_build(reply, McuComMsgType::CmdResp, 40, req.sn);
+        assert!(frames.len() > 2);
+        state.record_reply(&req, &[vec![1]]);
+        state.record_reply(&req, &frames);
+        let Delivery::Duplicate(replies) = state.check(&req) else {
+            panic!("duplicate not detected");
+        };
+        assert_eq!(replies.len(), frames.len() + 1);
+        // 重放的分片能重新拼成原应答
+        let mut rebuilt = None;
+        for frame in &replies[1..] {
+            let ParseResult::Success(pack) = parse_package_head(frame) else {
+                panic!("bad frame");
+            };
+            rebuilt = fragment_reassemble(pack).or(rebuilt);
+        }
+        assert_eq!(rebuilt.unwrap().as_cmd().unwrap().data, data);
+    }
+
+    #[test]
+    fn window_forgets_old_sns() {
+        let config = ReliableConfig {
+            window: 4,
+            ..Default::default()
+        };
+        let mut state = ReliableState::new(config);
+        for sn in 1..=5 {
+            assert!(matches!(state.check(&head(2, sn)), Delivery::New));
+        }
+        assert!(matches!(state.check(&head(2, 1)), Delivery::New));
+        assert!(matches!(state.check(&head(2, 5)), Delivery::Duplicate(_)));
+    }
+
+    #[tokio::test]
+    async fn request_is_retransmitted_with_same_sn() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let policy = RetransmitPolicy {
+            retries: 2,
+            timeout: Duration::from_millis(50),
+        };
+        let data = ComPackage::Cmd(CmdPackage::new(CmdType::DeepSleep as u16, &[]));
+        let req = tokio::spawn({
+            let tx = tx.clone();
+            async move { reliable_request_with(data, McuComMsgType::Cmd, policy, &tx).await }
+        });
+        let first = rx.recv().await.unwrap();
+        let second = rx.recv().await.unwrap();
+        assert_eq!(first, second);
+        let ParseResult::Success(pack) = parse_package_head(&second) else {
+            panic!("bad frame");
+        };
+        let (peer_tx, mut peer_rx) = mpsc::channel(8);
+        common_respond(CmdType::DeepSleepResp, crate::command::payload::CmdStatus::Ok, &pack.head, &peer_tx).await;
+        let ParseResult::Success(resp) = parse_package_head(&peer_rx.recv().await.unwrap()) else {
+            panic!("bad frame");
+        };
+        protocol_dispatch(resp, &tx).await;
+        let resp = req.await.unwrap().unwrap();
+        assert_eq!(resp.head.src_sn, pack.head.sn);
+    }
+}
@@ -1,13 +1,29 @@
-use crate::communication::{protocol::*, types::*};
+use crate::communication::{frame::FrameDecoder, protocol::*};
 use crate::config::ini_parse;
-use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Instant};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, OnceLock},
+    time::Instant,
+};
 
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream},
-    sync::{Mutex, mpsc},
+    net::TcpListener,
+    sync::{Mutex, mpsc, oneshot, watch},
 };
 
+const DEFAULT_LISTEN_PORT: u16 = 9999;
+static SERVER_CTL: OnceLock<mpsc::Sender<ServerCtl>> = OnceLock::new();
+
+enum ServerCtl {
+    // 应答新地址是否绑定成功
+    Rebind(SocketAddr, oneshot::Sender<bool>),
+    CloseSessions,
+    // 应答当前所有会话的发送端
+    Sessions(oneshot::Sender<Vec<mpsc::Sender<Vec<u8>>>>),
+}
+
 impl ServerState {
     fn new() -> Self {
         Self {
@@ -25,13 +41,15 @@ impl ServerState {
         );
     }
     fn remove(&mut self, addr: &SocketAddr) {
-        self.clients.remove(addr);
+        if let Some(client) = self.clients.remove(addr) {
+            println!("{} disconnected after {:?}", client.addr, client.connected_at.elapsed());
+        }
     }
     fn online_count(&self) -> usize {
         self.clients.len()
     }
-    fn get_tx(&self, addr: &SocketAddr) -> Option<&mpsc::Sender<Vec<u8>>> {
-        self.clients.get(addr).map(|c| &c.tx)
+    fn senders(&self) -> Vec<mpsc::Sender<Vec<u8>>> {
+        self.clients.values().map(|c| c.tx.clone()).collect()
     }
 }
 
@@ -44,15 +62,95 @@ struct Client {
 struct ServerState {
     clients: HashMap<SocketAddr, Client>,
 }
+/// 监听地址取自 `network/listen_ip` 和 `network/listen_port`，未配置时
+/// 和以前一样监听 `network/local_ip:9999`
+pub fn tcp_listen_addr() -> Option<SocketAddr> {
+    let ip = ini_parse::ini_get_ini_config("network", "listen_ip")
+        .or_else(|| ini_parse::ini_get_ini_config("network", "local_ip"))?
+        .parse::<std::net::Ipv4Addr>()
+        .ok()?;
+    let port = ini_parse::ini_get_ini_config("network", "listen_port")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_LISTEN_PORT);
+    Some(SocketAddr::from((ip, port)))
+}
+
+/// Moves the listener to `addr`. The new address is bound before the old
+/// listener is released; on failure the old one keeps serving and None
+/// is returned. Open sessions stay up, see `tcp_server_close_sessions`.
+pub async fn tcp_server_rebind(addr: SocketAddr) -> Option<i32> {
+    let (done_tx, done_rx) = oneshot::channel();
+    SERVER_CTL.get()?.send(ServerCtl::Rebind(addr, done_tx)).await.ok()?;
+    done_rx.await.ok()?.then_some(0)
+}
+
+/// Closes every open session the same way, but keeps listening.
+pub async fn tcp_server_close_sessions() -> Option<i32> {
+    SERVER_CTL.get()?.send(ServerCtl::CloseSessions).await.ok()?;
+    Some(0)
+}
+
+/// Senders of every open session, for messages ini-proc starts itself.
+pub async fn tcp_server_sessions() -> Vec<mpsc::Sender<Vec<u8>>> {
+    let (done_tx, done_rx) = oneshot::channel();
+    let Some(ctl) = SERVER_CTL.get() else {
+        return Vec::new();
+    };
+    if ctl.send(ServerCtl::Sessions(done_tx)).await.is_err() {
+        return Vec::new();
+    }
+    done_rx.await.unwrap_or_default()
+}
+
 pub async fn tcp_server_start() -> Option<i32> {
-    let mut ip_port = ini_parse::ini_get_ini_config("network", "local_ip")?;
-    ip_port.push_str(":9999");
+    let addr = tcp_listen_addr()?;
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            println!("tcp server bind {} failed: {}", addr, err);
+            return None;
+        }
+    };
+    let (ctl_tx, ctl_rx) = mpsc::channel(1);
+    SERVER_CTL.set(ctl_tx).ok()?;
+    tcp_server_run(listener, ctl_rx).await;
+    Some(0)
+}
 
+async fn tcp_server_run(mut listener: TcpListener, mut ctl_rx: mpsc::Receiver<ServerCtl>) {
     let shared_state = SharedState::new(Mutex::new(ServerState::new()));
-
-    let listener = TcpListener::bind(ip_port).await.unwrap();
+    // 每次关闭会话加一，通知现有会话关闭
+    let (close_tx, _) = watch::channel(0u64);
+    println!("tcp server listen on {:?}", listener.local_addr());
     loop {
-        let (tcp_stream, socket_addr) = listener.accept().await.unwrap();
+        let (tcp_stream, socket_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    println!("tcp accept failed: {}", err);
+                    continue;
+                }
+            },
+            Some(ctl) = ctl_rx.recv() => {
+                match ctl {
+                    ServerCtl::Rebind(addr, done) => {
+                        let ok = match tcp_server_swap(&listener, addr).await {
+                            Some(new) => {
+                                listener = new;
+                                true
+                            }
+                            None => false,
+                        };
+                        let _ = done.send(ok);
+                    }
+                    ServerCtl::CloseSessions => close_tx.send_modify(|generation| *generation += 1),
+                    ServerCtl::Sessions(done) => {
+                        let _ = done.send(shared_state.lock().await.senders());
+                    }
+                }
+                continue;
+            }
+        };
         let (tx, rx) = mpsc::channel::<Vec<u8>>(32);
 
         let (recv, send) = tcp_stream.into_split();
@@ -66,40 +164,154 @@ pub async fn tcp_server_start() -> Option<i32> {
             serverstate.clients.len(),
             serverstate.online_count()
         );
+        let close_rx = close_tx.subscribe();
         tokio::spawn(async move {
-            process(recv, tx, tmp_shared_state, &socket_addr).await;
+            process(recv, tx, tmp_shared_state, &socket_addr, close_rx).await;
         });
+        let close_rx = close_tx.subscribe();
         tokio::spawn(async move {
-            server_send(send, rx).await;
+            server_send(send, rx, close_rx).await;
         });
     }
 }
 
+/// 先绑定新地址，成功后才替换旧的监听；失败时旧监听不受影响
+async fn tcp_server_swap(old: &TcpListener, addr: SocketAddr) -> Option<TcpListener> {
+    let old_addr = old.local_addr().ok();
+    match TcpListener::bind(addr).await {
+        Ok(listener) => {
+            println!("tcp server rebind {:?} -> {}", old_addr, addr);
+            Some(listener)
+        }
+        Err(err) => {
+            println!("tcp server rebind {} failed: {}, keep {:?}", addr, err, old_addr);
+            None
+        }
+    }
+}
+
 async fn process(
     recv: tokio::net::tcp::OwnedReadHalf,
     tx: mpsc::Sender<Vec<u8>>,
     shared_state: SharedState,
-    addr : &SocketAddr
+    addr : &SocketAddr,
+    mut close_rx: watch::Receiver<u64>,
 ) {
     let mut recv = recv;
-    let mut tx = tx;
+    let mut decoder = FrameDecoder::new();
+    let mut buf = [0; 1024];
     loop {
-        let mut buf = [0; 1024];
-        match recv.read(&mut buf).await {
-            Ok(0) | Err(_) => {shared_state.lock().await.remove(addr); break},
+        let read = tokio::select! {
+            read = recv.read(&mut buf) => read,
+            _ = close_rx.changed() => {
+                println!("{} closed by server", addr);
+                shared_state.lock().await.remove(addr);
+                break;
+            }
+        };
+        match read {
+            Ok(0) | Err(_) => {
+                if decoder.buffered() > 0 {
+                    println!("{} closed with {} undecoded bytes", addr, decoder.buffered());
+                }
+                shared_state.lock().await.remove(addr);
+                break;
+            }
             Ok(n) => {
-                protocol_parse(&buf, &tx).await;
+                decoder.push(&buf[..n]);
+                while let Some(pack) = decoder.next_package() {
+                    protocol_dispatch(pack, &tx).await;
+                }
             }
         }
         // let data = &rx.recv().await;
     }
 }
-async fn server_send(send: tokio::net::tcp::OwnedWriteHalf, rx: mpsc::Receiver<Vec<u8>>) {
+async fn server_send(
+    send: tokio::net::tcp::OwnedWriteHalf,
+    rx: mpsc::Receiver<Vec<u8>>,
+    mut close_rx: watch::Receiver<u64>,
+) {
     let mut rx = rx;
     let mut send = send;
-    while let Some(data) = rx.recv().await {
+    loop {
+        let data = tokio::select! {
+            data = rx.recv() => match data {
+                Some(data) => data,
+                None => break,
+            },
+            _ = close_rx.changed() => {
+                // 已排队的应答先发完，再正常关闭(FIN)
+                while let Ok(data) = rx.try_recv() {
+                    if send.write_all(&data).await.is_err() {
+                        return;
+                    }
+                }
+                let _ = send.shutdown().await;
+                return;
+            }
+        };
         if send.write_all(&data).await.is_err() {
             break;
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communication::heartbeat::heartbeat_local_status;
+    use crate::communication::types::*;
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn rebind_flushes_and_closes_sessions() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let old_addr = listener.local_addr().unwrap();
+        let (ctl_tx, ctl_rx) = mpsc::channel(1);
+        tokio::spawn(tcp_server_run(listener, ctl_rx));
+
+        let mut client = TcpStream::connect(old_addr).await.unwrap();
+        // 收到心跳应答说明会话已建立
+        let hb = McuComPackage {
+            head: McuComPackageHead {
+                pack_head_flg: PACKAGE_HEAD_FLAG,
+                data_len: 0,
+                crc: 0,
+                mcu_id: 4,
+                sn: 1,
+                src_sn: 0,
+                msg_type: McuComMsgType::HeartBeat as u16,
+            },
+            data: ComPackage::Heartbeat(heartbeat_local_status(None)),
+        };
+        client.write_all(&hb.encode()).await.unwrap();
+        let mut reply = vec![0u8; HEAD_SIZE + HEARTBEAT_SIZE + 4];
+        client.read_exact(&mut reply).await.unwrap();
+
+        // 新地址被占用时保留旧监听和会话
+        let busy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (done_tx, done_rx) = oneshot::channel();
+        ctl_tx.send(ServerCtl::Rebind(busy.local_addr().unwrap(), done_tx)).await.unwrap();
+        assert!(!done_rx.await.unwrap());
+        assert!(TcpStream::connect(old_addr).await.is_ok());
+        client.write_all(&hb.encode()).await.unwrap();
+        client.read_exact(&mut reply).await.unwrap();
+
+        // 占用一个空闲端口号后释放，作为新地址
+        let new_addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let (done_tx, done_rx) = oneshot::channel();
+        ctl_tx.send(ServerCtl::Rebind(new_addr, done_tx)).await.unwrap();
+        assert!(done_rx.await.unwrap());
+        ctl_tx.send(ServerCtl::CloseSessions).await.unwrap();
+
+        let mut buf = [0u8; 16];
+        let n = tokio::time::timeout(std::time::Duration::from_secs(2), client.read(&mut buf))
+            .await
+            .expect("session not closed")
+            .unwrap();
+        assert_eq!(n, 0);
+        assert!(TcpStream::connect(new_addr).await.is_ok());
+        assert!(TcpStream::connect(old_addr).await.is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive)]
 pub enum Direction {
     Top = 0,
     TopLeft = 1,
@@ -13,12 +13,13 @@ pub enum Direction {
     BottomLeft,   // 左下  7
     BottomCenter, // 中下  8
     BottomRight,  // 右下  9
+    #[allow(clippy::upper_case_acronyms)]
     EXTRA,
     CoordinateAll = 11,
 }
 
 #[repr(u16)] // 对应C的enum大小
-#[derive(Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, IntoPrimitive, TryFromPrimitive)]
 pub enum McuComMsgType {
     Unknown = 0,
     HeartBeat = 1,
@@ -37,8 +38,22 @@ pub enum McuComMsgType {
     CmdResp = 14,
 }
 
+impl McuComMsgType {
+    // 请求类型为奇数，对应应答为请求类型加一
+    pub fn is_response(self) -> bool {
+        let v = self as u16;
+        v != 0 && v.is_multiple_of(2)
+    }
+    pub fn response_type(self) -> Option<McuComMsgType> {
+        if self == McuComMsgType::Unknown || self.is_response() {
+            return None;
+        }
+        McuComMsgType::try_from(self as u16 + 1).ok()
+    }
+}
+
 #[repr(u16)] 
-#[derive(Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, IntoPrimitive, TryFromPrimitive)]
 pub enum CmdType {
     Unknown = 0,
     RemoteCapture,                    // 拍照指令
@@ -86,7 +101,21 @@ pub enum CmdType {
     MaxCount,
 }
 
-#[repr(C)]
+impl CmdType {
+    // 请求为奇数，应答为请求 + 1
+    pub fn is_response(self) -> bool {
+        let v = self as u16;
+        v != 0 && v.is_multiple_of(2)
+    }
+    pub fn response_type(self) -> Option<CmdType> {
+        if self == CmdType::Unknown || self == CmdType::MaxCount || self.is_response() {
+            return None;
+        }
+        CmdType::try_from(self as u16 + 1).ok()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct McuComPackage {
     pub head: McuComPackageHead,
     pub data: ComPackage,
@@ -94,41 +123,28 @@ pub struct McuComPackage {
 
 impl McuComPackage {
     pub fn as_cmd(&self) -> Option<&CmdPackage> {
-        if self.head.msg_type == McuComMsgType::Cmd as u16 {
-            unsafe {
-                return Some(&self.data.cmd_package);
-            }
-        } else {
-            return None;
+        match &self.data {
+            ComPackage::Cmd(cmd) => Some(cmd),
+            _ => None,
         }
     }
     pub fn as_heartbeat(&self) -> Option<&HeartbeatPackage> {
-        if self.head.msg_type == McuComMsgType::HeartBeat as u16 {
-            unsafe {
-                return Some(&self.data.heartbeat_package);
-            }
-        } else {
-            return None;
+        match &self.data {
+            ComPackage::Heartbeat(hb) => Some(hb),
+            _ => None,
         }
     }
+    #[cfg(test)]
     pub fn as_heartbeat_reply(&self) -> Option<&HeartbeadReplyPackage> {
-        if self.head.msg_type == McuComMsgType::HeartBeatRep as u16 {
-            unsafe {
-                return Some(&self.data.heartbeat_reply_package);
-            }
-        } else {
-            return None;
+        match &self.data {
+            ComPackage::HeartbeatReply(rep) => Some(rep),
+            _ => None,
         }
     }
-    pub fn bytes_to_struct<T>(data: &[u8]) -> T {
-        unsafe { std::ptr::read(data.as_ptr() as *const T) }
-    }
-    pub fn struct_to_bytes<T>(data: &T) -> Vec<u8> {
-        unsafe {
-            let size = std::mem::size_of::<T>();
-            let ptr = data as *const T as *const u8;
-            let slice = std::slice::from_raw_parts(ptr, size);
-            return slice.to_vec();
+    pub fn as_raw(&self) -> Option<&[u8]> {
+        match &self.data {
+            ComPackage::Raw(raw) => Some(raw),
+            _ => None,
         }
     }
 }
@@ -147,22 +163,34 @@ pub const CRC_OFFSET: usize = 5;
 pub const HEAD_SIZE: usize = size_of::<McuComPackageHead>();
 const _: () = assert!(HEAD_SIZE == 12);
 
-#[repr(C)]
-pub union ComPackage {
-    pub cmd_package: CmdPackage,
-    pub heartbeat_package: HeartbeatPackage,
-    pub heartbeat_reply_package: HeartbeadReplyPackage,
+// 报文数据部分，线路格式见 codec.rs
+#[derive(Debug, Clone)]
+pub enum ComPackage {
+    Cmd(CmdPackage),
+    Heartbeat(HeartbeatPackage),
+    HeartbeatReply(HeartbeadReplyPackage),
+    Raw(Vec<u8>), // 由各子系统自行解析的数据
 }
-const _: () = assert!(std::mem::size_of::<ComPackage>() == 260);
+pub const COM_PACKAGE_MAX_SIZE: usize = CMD_HEADER_SIZE as usize + CMD_DATA_MAX;
 
-#[repr(C)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CmdPackage {
     pub cmd_type: u16,
     pub cmd_data_len: u16,
-    pub data: [u8; 256],
+    pub data: Vec<u8>, // 单帧最多 CMD_DATA_MAX，分片重组后可更长
 }
 pub const CMD_HEADER_SIZE: u16 = 4;
+pub const CMD_DATA_MAX: usize = 256;
+
+impl CmdPackage {
+    pub fn new(cmd_type: u16, data: &[u8]) -> Self {
+        Self {
+            cmd_type,
+            cmd_data_len: data.len() as u16,
+            data: data.to_vec(),
+        }
+    }
+}
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct HeartbeatPackage {
@@ -186,10 +214,11 @@ pub struct HeartbeatPackage {
     pub camera_status: u8,       // 摄像头状态
     pub tf_status: u8,           // 内存卡状态 0正常 1故障
 }
+pub const HEARTBEAT_SIZE: usize = 36;
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct HeartbeadReplyPackage {
-    src_sn: u16,
-    reserve: u16,
-    heartbeat_package: HeartbeatPackage,
+    pub src_sn: u16,
+    pub reserve: u16,
+    pub heartbeat_package: HeartbeatPackage,
 }
@@ -1,15 +1,31 @@
+use super::validate::{ConfigError, ini_is_masked_secret, ini_validate};
 use crate::common::FW_VERSION;
 use ini::Ini;
-use std::sync::{OnceLock, RwLock};
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    sync::{
+        Mutex, OnceLock, RwLock,
+        atomic::{AtomicU32, Ordering},
+    },
+};
 
 static CONFIG: OnceLock<RwLock<Ini>> = OnceLock::new();
+static CONFIG_FILE: OnceLock<String> = OnceLock::new();
+// 仅在内存中补齐的默认键, 保存时不写回文件
+static FILLED: OnceLock<Mutex<HashSet<(String, String)>>> = OnceLock::new();
+// 保存串行化: 拷贝、写入、改名之间不能插入另一次保存
+static SAVE_LOCK: Mutex<()> = Mutex::new(());
+static SAVE_SEQ: AtomicU32 = AtomicU32::new(0);
 pub fn ini_init_config(ini_filename: &str) -> Option<i32> {
+    let mut filled = HashSet::new();
     let ini = if let Ok(ini) = Ini::load_from_file(ini_filename) {
         if ini.len() == 1 {
             eprintln!("No sections found, writing default config.");
             ini_setting_default(ini_filename);
         }
-        let ini = Ini::load_from_file(ini_filename).ok()?;
+        let mut ini = Ini::load_from_file(ini_filename).ok()?;
+        filled = ini_fill_defaults(&mut ini);
         for (sec, prop) in ini.iter() {
             println!("Section: {:?}", sec);
             // if prop.is_empty() {
@@ -28,9 +44,58 @@ pub fn ini_init_config(ini_filename: &str) -> Option<i32> {
         println!("ini_init_config ok.");
         RwLock::new(ini)
     });
+    CONFIG_FILE.get_or_init(|| ini_filename.to_string());
+    if !filled.is_empty() {
+        println!("{} missing keys filled from defaults in memory.", filled.len());
+    }
+    FILLED.get_or_init(|| Mutex::new(filled));
     Some(0)
 }
 
+/// Adds keys a newer firmware expects but an older INI file lacks. The
+/// file is left alone: a filled key is only written back once something
+/// sets it explicitly.
+fn ini_fill_defaults(ini: &mut Ini) -> HashSet<(String, String)> {
+    let mut filled = HashSet::new();
+    for (sec, prop) in ini_default_config().iter() {
+        for (k, v) in prop.iter() {
+            if ini.get_from(sec, k).is_none() {
+                ini.set_to(sec, k.to_string(), v.to_string());
+                filled.insert((sec.unwrap_or("").to_string(), k.to_string()));
+            }
+        }
+    }
+    filled
+}
+
+fn ini_filled() -> &'static Mutex<HashSet<(String, String)>> {
+    FILLED.get_or_init(|| Mutex::new(HashSet::new()))
+}
+
+fn ini_is_filled(section: &str, key: &str) -> bool {
+    ini_filled()
+        .lock()
+        .is_ok_and(|f| f.contains(&(section.to_string(), key.to_string())))
+}
+
+fn ini_unmark_filled(section: &str, key: &str) {
+    if let Ok(mut filled) = ini_filled().lock() {
+        filled.remove(&(section.to_string(), key.to_string()));
+    }
+}
+
+/// 测试共用一个临时配置文件
+#[cfg(test)]
+pub fn ini_test_init() -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("ini-proc-test-{}.ini", std::process::id()));
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| {
+        let _ = fs::remove_file(&path);
+        ini_init_config(path.to_str().unwrap());
+    });
+    path
+}
+
 pub fn ini_get_ini_config(section: &str, key: &str) -> Option<String> {
     CONFIG
         .get()?
@@ -39,7 +104,157 @@ pub fn ini_get_ini_config(section: &str, key: &str) -> Option<String> {
         .get_from(Some(section), key)
         .map(|v| v.to_string())
 }
+/// 空字符串表示无名的全局段
+fn ini_section(section: &str) -> Option<&str> {
+    (!section.is_empty()).then_some(section)
+}
+
+/// Like `ini_get_ini_config`, but ignores defaults that only exist in
+/// memory: for callers that must not act on a guessed value.
+pub fn ini_get_stored_config(section: &str, key: &str) -> Option<String> {
+    if ini_is_filled(section, key) {
+        return None;
+    }
+    ini_get_ini_config(section, key)
+}
+
+pub fn ini_get_ini_config_in(section: &str, key: &str) -> Option<String> {
+    CONFIG
+        .get()?
+        .read()
+        .ok()?
+        .get_from(ini_section(section), key)
+        .map(|v| v.to_string())
+}
+
+pub fn ini_get_sections() -> Option<Vec<String>> {
+    let ini = CONFIG.get()?.read().ok()?;
+    Some(ini.sections().map(|s| s.unwrap_or("").to_string()).collect())
+}
+
+pub fn ini_get_section_keys(section: &str) -> Option<Vec<String>> {
+    let ini = CONFIG.get()?.read().ok()?;
+    let prop = ini.section(ini_section(section))?;
+    Some(prop.iter().map(|(k, _)| k.to_string()).collect())
+}
+
+pub fn ini_get_section_items(section: &str) -> Option<Vec<(String, String)>> {
+    let ini = CONFIG.get()?.read().ok()?;
+    let prop = ini.section(ini_section(section))?;
+    Some(prop.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+}
+
+/// Validates and sets an existing key in memory. Call `ini_save_config`
+/// to persist. A masked secret leaves the stored value as it is.
+pub fn ini_set_ini_config(section: &str, key: &str, value: &str) -> Result<(), ConfigError> {
+    let mut ini = CONFIG
+        .get()
+        .ok_or(ConfigError::NotInitialized)?
+        .write()
+        .map_err(|_| ConfigError::NotInitialized)?;
+    let section = ini_section(section);
+    let exists = ini.get_from(section, key).is_some();
+    if ini_is_masked_secret(section, key, value) {
+        return if exists { Ok(()) } else { Err(ConfigError::NotFound) };
+    }
+    ini_validate(section, key, value, exists)?;
+    ini.set_to(section, key.to_string(), value.to_string());
+    ini_unmark_filled(section.unwrap_or(""), key);
+    Ok(())
+}
+
+/// Like `ini_set_ini_config`, but adds the key when an older INI lacks
+/// it. For settings ini-proc owns itself, not for remote writes.
+pub fn ini_put_ini_config(section: &str, key: &str, value: &str) -> Result<(), ConfigError> {
+    let mut ini = CONFIG
+        .get()
+        .ok_or(ConfigError::NotInitialized)?
+        .write()
+        .map_err(|_| ConfigError::NotInitialized)?;
+    let section = ini_section(section);
+    ini_validate(section, key, value, true)?;
+    ini.set_to(section, key.to_string(), value.to_string());
+    ini_unmark_filled(section.unwrap_or(""), key);
+    Ok(())
+}
+
+/// Sets several keys of one section: either all values are valid and
+/// set, or nothing changes. Masked secrets keep their stored values.
+pub fn ini_set_ini_configs(section: &str, items: &[(String, String)]) -> Result<(), ConfigError> {
+    let mut ini = CONFIG
+        .get()
+        .ok_or(ConfigError::NotInitialized)?
+        .write()
+        .map_err(|_| ConfigError::NotInitialized)?;
+    let section = ini_section(section);
+    let mut changes = Vec::with_capacity(items.len());
+    for (key, value) in items {
+        let exists = ini.get_from(section, key).is_some();
+        if ini_is_masked_secret(section, key, value) {
+            if !exists {
+                return Err(ConfigError::NotFound);
+            }
+            continue;
+        }
+        ini_validate(section, key, value, exists)?;
+        changes.push((key, value));
+    }
+    for (key, value) in changes {
+        ini.set_to(section, key.to_string(), value.to_string());
+        ini_unmark_filled(section.unwrap_or(""), key);
+    }
+    Ok(())
+}
+
+/// Writes the config back to its file via a temp file and rename, so a
+/// power cut never leaves a half-written INI. Defaults filled in memory
+/// and never set are left out. Concurrent saves are serialized, so the
+/// last one to finish always holds the latest config.
+pub fn ini_save_config() -> Result<(), ConfigError> {
+    let filename = CONFIG_FILE.get().ok_or(ConfigError::NotInitialized)?;
+    let _save = SAVE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut ini = CONFIG
+        .get()
+        .ok_or(ConfigError::NotInitialized)?
+        .read()
+        .map_err(|_| ConfigError::NotInitialized)?
+        .clone();
+    if let Ok(filled) = ini_filled().lock() {
+        for (section, key) in filled.iter() {
+            ini.delete_from(ini_section(section), key);
+        }
+    }
+    let tmp = format!(
+        "{}.{}.{}.tmp",
+        filename,
+        std::process::id(),
+        SAVE_SEQ.fetch_add(1, Ordering::Relaxed)
+    );
+    let ret = File::create(&tmp)
+        .and_then(|mut file| {
+            ini.write_to(&mut file)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp, filename));
+    if let Err(err) = ret {
+        eprintln!("save config {} failed: {}", filename, err);
+        let _ = fs::remove_file(&tmp);
+        return Err(ConfigError::PersistFailed);
+    }
+    Ok(())
+}
+
 fn ini_setting_default(ini_filename: &str) -> Option<Ini> {
+    let conf = ini_default_config();
+    if let Err(err) = conf.write_to_file(ini_filename) {
+        eprintln!("Error: {}", err);
+        None
+    } else {
+        Some(conf)
+    }
+}
+
+fn ini_default_config() -> Ini {
     let mut conf = Ini::new();
     conf.with_section(None::<String>).set("soc", "mc6357");
 
@@ -59,7 +274,10 @@ fn ini_setting_default(ini_filename: &str) -> Option<Ini> {
         .set("recorder", "off")
         .set("yolov5s", "off")
         .set("rtmp_dev", "0")
-        .set("coordinate", "1");
+        .set("coordinate", "1")
+        .set("clip_pre_s", "5")
+        .set("clip_duration_s", "15")
+        .set("time_zone", "8");
 
     conf.with_section(Some("gpiopins"))
         .set("camctlbase", "37")
@@ -82,6 +300,7 @@ fn ini_setting_default(ini_filename: &str) -> Option<Ini> {
         .set("ftp_pwd", "hhd@123.com")
         .set("local_ip","192.168.30.214")
         .set("local_gateway","192.168.30.254")
+        .set("local_netmask", "255.255.255.0")
         .set("link_type", "ETH")
         .set("link_mode", "static")
         .set("tcp_server_ip", "192.168.30.171")
@@ -102,10 +321,58 @@ fn ini_setting_default(ini_filename: &str) -> Option<Ini> {
         .set("devicePort", "5060")
         .set("alertId", "0");
 
-    if let Err(err) = conf.write_to_file(ini_filename) {
-        eprintln!("Error: {}", err);
-        None
-    } else {
-        Some(conf)
+    conf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn put_adds_missing_keys() {
+        ini_test_init();
+        assert_eq!(ini_set_ini_config("system", "put_test", "1"), Err(ConfigError::NotFound));
+        assert_eq!(ini_put_ini_config("system", "put_test", "1"), Ok(()));
+        assert_eq!(ini_get_ini_config("system", "put_test").as_deref(), Some("1"));
+        assert_eq!(ini_put_ini_config("system", "time_zone", "20"), Err(ConfigError::InvalidValue));
+    }
+
+    #[test]
+    fn filled_defaults_are_not_saved() {
+        let path = ini_test_init();
+        ini_put_ini_config("system", "fill_test", "1").unwrap();
+        ini_filled()
+            .lock()
+            .unwrap()
+            .insert(("system".to_string(), "fill_test".to_string()));
+        ini_save_config().unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("fill_test"));
+        assert_eq!(ini_get_ini_config("system", "fill_test").as_deref(), Some("1"));
+        assert_eq!(ini_get_stored_config("system", "fill_test"), None);
+
+        ini_put_ini_config("system", "fill_test", "2").unwrap();
+        ini_save_config().unwrap();
+        assert!(fs::read_to_string(&path).unwrap().contains("fill_test=2"));
+        assert_eq!(ini_get_stored_config("system", "fill_test").as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn concurrent_saves_keep_the_latest() {
+        let path = ini_test_init();
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    ini_put_ini_config("system", &format!("save_test_{}", i), "1").unwrap();
+                    ini_save_config().unwrap();
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        let saved = fs::read_to_string(&path).unwrap();
+        for i in 0..8 {
+            assert!(saved.contains(&format!("save_test_{}=1", i)));
+        }
     }
 }
@@ -1 +1,2 @@
-pub mod ini_parse;
\ No newline at end of file
+pub mod ini_parse;
+pub mod validate;
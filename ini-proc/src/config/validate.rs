@@ -0,0 +1,127 @@
+//! 配置项校验
+//!
+//! Remote writes may only change keys that already exist in the INI, and
+//! the value must parse as the key's kind.
+
+use crate::system::osd::osd_coordinate_parse;
+use std::net::Ipv4Addr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigError {
+    NotInitialized,
+    NotFound,
+    ReadOnly,
+    InvalidValue,
+    PersistFailed,
+}
+
+enum ValueKind {
+    ReadOnly,
+    Ipv4,
+    Port,
+    OnOff,
+    Choice(&'static [&'static str]),
+    Range(i64, i64),
+    Coordinate,
+    /// 十进制数字串，长度为其中之一 (GB/T 28181 编码)
+    Digits(&'static [usize]),
+    Text,
+}
+
+fn ini_value_kind(section: Option<&str>, key: &str) -> ValueKind {
+    match (section.unwrap_or(""), key) {
+        ("", "soc") | ("system", "FW_VERSION") => ValueKind::ReadOnly,
+        ("network", "local_ip" | "local_gateway" | "local_netmask" | "tcp_server_ip" | "listen_ip") => {
+            ValueKind::Ipv4
+        }
+        ("network", "link_type") => ValueKind::Choice(&["ETH", "4G"]),
+        ("network", "link_mode") => ValueKind::Choice(&["static", "dhcp"]),
+        ("gb28181", "serverIp") => ValueKind::Ipv4,
+        ("network", "tcp_server_port" | "listen_port") => ValueKind::Port,
+        ("gb28181", "serverPort" | "devicePort") => ValueKind::Port,
+        ("system", "recorder" | "yolov5s") | ("gb28181", "status") => ValueKind::OnOff,
+        ("system", "LOG_LEVEL") => ValueKind::Range(0, 7),
+        ("system", "time_zone") => ValueKind::Range(-12, 14),
+        ("system", "coordinate") => ValueKind::Coordinate,
+        ("system", "clip_pre_s") => ValueKind::Range(0, 60),
+        ("system", "clip_duration_s") => ValueKind::Range(1, 300),
+        ("network", "interval") => ValueKind::Range(1, 3600),
+        ("gb28181", "regTimeOut" | "heartBeat") => ValueKind::Range(1, 86400),
+        ("gb28181", "serverId" | "deviceId") => ValueKind::Digits(&[20]),
+        ("gb28181", "domain") => ValueKind::Digits(&[10]),
+        // 0 表示未配置报警通道
+        ("gb28181", "alertId") => ValueKind::Digits(&[1, 20]),
+        ("gb28181", "codeStream") => ValueKind::Choice(&["main", "sub"]),
+        ("gb28181", "encode") => ValueKind::Choice(&["enable", "disable"]),
+        _ => ValueKind::Text,
+    }
+}
+
+/// 密码类配置，远程读取时不返回明文
+pub fn ini_is_secret(section: Option<&str>, key: &str) -> bool {
+    matches!(
+        (section.unwrap_or(""), key),
+        ("network", "ftp_pwd") | ("quectel", "quectel_pwd") | ("gb28181", "passWord")
+    )
+}
+
+pub const SECRET_MASK: &str = "******";
+
+/// A secret written back as it was shown, i.e. masked. Setting it keeps
+/// the stored value.
+pub fn ini_is_masked_secret(section: Option<&str>, key: &str, value: &str) -> bool {
+    value == SECRET_MASK && ini_is_secret(section, key)
+}
+
+/// `value` as it may be shown remotely or logged.
+pub fn ini_mask_secret<'a>(section: Option<&str>, key: &str, value: &'a str) -> &'a str {
+    if ini_is_secret(section, key) { SECRET_MASK } else { value }
+}
+
+/// Checks `value` for `key`; `exists` is whether the key is already set.
+pub fn ini_validate(section: Option<&str>, key: &str, value: &str, exists: bool) -> Result<(), ConfigError> {
+    if !exists {
+        return Err(ConfigError::NotFound);
+    }
+    let ok = match ini_value_kind(section, key) {
+        ValueKind::ReadOnly => return Err(ConfigError::ReadOnly),
+        ValueKind::Ipv4 => value.parse::<Ipv4Addr>().is_ok(),
+        ValueKind::Port => value.parse::<u16>().is_ok_and(|p| p != 0),
+        ValueKind::OnOff => matches!(value, "on" | "off"),
+        ValueKind::Choice(values) => values.contains(&value),
+        ValueKind::Range(min, max) => value.parse::<i64>().is_ok_and(|v| (min..=max).contains(&v)),
+        ValueKind::Digits(lens) => lens.contains(&value.len()) && value.bytes().all(|b| b.is_ascii_digit()),
+        ValueKind::Coordinate => osd_coordinate_parse(value).is_some(),
+        // 换行会破坏 INI 文件格式
+        ValueKind::Text => !value.contains(['\n', '\r']),
+    };
+    if ok { Ok(()) } else { Err(ConfigError::InvalidValue) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_checked_by_kind() {
+        let v = |section, key, value| ini_validate(Some(section), key, value, true);
+        assert_eq!(v("network", "tcp_server_ip", "10.0.0.2"), Ok(()));
+        assert_eq!(v("network", "tcp_server_ip", "10.0.0"), Err(ConfigError::InvalidValue));
+        assert_eq!(v("network", "tcp_server_port", "0"), Err(ConfigError::InvalidValue));
+        assert_eq!(v("system", "time_zone", "-5"), Ok(()));
+        assert_eq!(v("system", "time_zone", "15"), Err(ConfigError::InvalidValue));
+        assert_eq!(v("system", "recorder", "yes"), Err(ConfigError::InvalidValue));
+        assert_eq!(v("network", "link_mode", "dhcp"), Ok(()));
+        assert_eq!(v("network", "link_type", "WIFI"), Err(ConfigError::InvalidValue));
+        assert_eq!(v("system", "coordinate", "11"), Ok(()));
+        assert_eq!(v("system", "coordinate", "10"), Err(ConfigError::InvalidValue));
+        assert_eq!(v("gb28181", "deviceId", "44010200492504240018"), Ok(()));
+        assert_eq!(v("gb28181", "deviceId", "4401020049250424001x"), Err(ConfigError::InvalidValue));
+        assert_eq!(v("gb28181", "domain", "44010200492"), Err(ConfigError::InvalidValue));
+        assert_eq!(v("gb28181", "alertId", "0"), Ok(()));
+        assert_eq!(v("system", "FW_VERSION", "x"), Err(ConfigError::ReadOnly));
+        assert_eq!(v("quectel", "apn", "a\nb"), Err(ConfigError::InvalidValue));
+        assert_eq!(ini_validate(None, "soc", "x", true), Err(ConfigError::ReadOnly));
+        assert_eq!(ini_validate(Some("system"), "nope", "1", false), Err(ConfigError::NotFound));
+    }
+}
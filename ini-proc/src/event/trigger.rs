@@ -0,0 +1,143 @@
+//! 事件触发
+//!
+//! Peer state changes (see `peer_state`) are matched against trigger
+//! rules; each matching rule fires its actions. The subsystems that can
+//! perform an action (capture, recording) register a handler for it with
+//! `event_register_action`. An action without a handler is only logged.
+
+use crate::communication::peer_state::{PeerEvent, peer_state_subscribe};
+use std::{
+    collections::HashMap,
+    sync::{OnceLock, RwLock},
+};
+use tokio::sync::broadcast::error::RecvError;
+
+static EVENT_TRIGGER: OnceLock<RwLock<EventTrigger>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventAction {
+    Capture, // 抓拍
+    Record,  // 事件录像
+}
+
+/// Handlers run on the event task and must not block; long work should
+/// be spawned.
+pub type ActionHandler = Box<dyn Fn(&PeerEvent) + Send + Sync>;
+
+pub(crate) struct TriggerRule {
+    pub name: &'static str,
+    pub matches: fn(&PeerEvent) -> bool,
+    pub actions: Vec<EventAction>,
+}
+
+pub(crate) fn default_rules() -> Vec<TriggerRule> {
+    vec![TriggerRule {
+        name: "lock_opened",
+        matches: PeerEvent::is_lock_opened,
+        actions: vec![EventAction::Capture, EventAction::Record],
+    }]
+}
+
+pub(crate) struct EventTrigger {
+    rules: Vec<TriggerRule>,
+    handlers: HashMap<EventAction, Vec<ActionHandler>>,
+}
+
+impl EventTrigger {
+    pub(crate) fn new(rules: Vec<TriggerRule>) -> Self {
+        Self {
+            rules,
+            handlers: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn register(&mut self, action: EventAction, handler: ActionHandler) {
+        self.handlers.entry(action).or_default().push(handler);
+    }
+
+    /// Runs the actions of every rule matching `event`, returns how many
+    /// handlers were called.
+    pub(crate) fn fire(&self, event: &PeerEvent) -> usize {
+        let mut called = 0;
+        for rule in self.rules.iter().filter(|r| (r.matches)(event)) {
+            for action in &rule.actions {
+                let handlers = self.handlers.get(action).map_or(&[][..], |h| h.as_slice());
+                println!(
+                    "event {:?} rule {} -> {:?}, {} handlers",
+                    event,
+                    rule.name,
+                    action,
+                    handlers.len()
+                );
+                for handler in handlers {
+                    handler(event);
+                    called += 1;
+                }
+            }
+        }
+        called
+    }
+}
+
+fn event_trigger() -> &'static RwLock<EventTrigger> {
+    EVENT_TRIGGER.get_or_init(|| RwLock::new(EventTrigger::new(default_rules())))
+}
+
+pub fn event_register_action(action: EventAction, handler: ActionHandler) {
+    if let Ok(mut trigger) = event_trigger().write() {
+        trigger.register(action, handler);
+    }
+}
+
+/// Starts the task feeding peer state events into the trigger rules.
+pub fn event_trigger_start() -> Option<i32> {
+    let mut rx = peer_state_subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    if let Ok(trigger) = event_trigger().read() {
+                        trigger.fire(&event);
+                    }
+                }
+                Err(RecvError::Lagged(n)) => println!("event trigger lagged, {} events lost", n),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+    Some(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering::Relaxed},
+    };
+
+    #[test]
+    fn lock_change_fires_capture_and_record() {
+        let mut trigger = EventTrigger::new(default_rules());
+        let count = Arc::new(AtomicUsize::new(0));
+        for action in [EventAction::Capture, EventAction::Record] {
+            let count = count.clone();
+            trigger.register(
+                action,
+                Box::new(move |_| {
+                    count.fetch_add(1, Relaxed);
+                }),
+            );
+        }
+
+        let lock = PeerEvent::Lock { old: 0x31, new: 0x30 };
+        assert_eq!(trigger.fire(&lock), 2);
+        let io = PeerEvent::Io {
+            old: 0,
+            new: 1,
+            changed: 1,
+        };
+        assert_eq!(trigger.fire(&io), 0);
+        assert_eq!(count.load(Relaxed), 2);
+    }
+}
@@ -0,0 +1,194 @@
+//! GPS 定位数据
+//!
+//! The MCU forwards its GNSS fixes with GpsData messages:
+//!
+//! | time_s u32 | lat i32 | lon i32 | altitude i16 | speed u16 | heading u16 | quality u8 | satellites u8 |
+//!
+//! lat/lon are in 1e-7 degree, altitude in metres, speed in 0.1 km/h and
+//! heading in 0.01 degree. Every message is answered with GpsDataResp
+//! `| status u8 |`. The latest valid fix is kept for OSD and event metadata.
+
+use super::track::gps_track_append;
+use crate::communication::codec::WireReader;
+use crate::communication::protocol::*;
+use crate::communication::types::*;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use std::{
+    sync::{OnceLock, RwLock},
+    time::SystemTime,
+};
+use tokio::sync::mpsc;
+
+pub const GPS_FIX_SIZE: usize = 20;
+static GPS_LATEST: OnceLock<RwLock<Option<GpsFix>>> = OnceLock::new();
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive)]
+pub enum GpsFixQuality {
+    Invalid = 0,
+    Gps = 1,
+    Dgps = 2,
+    Rtk = 4,
+    FloatRtk = 5,
+    Estimated = 6,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, IntoPrimitive)]
+pub enum GpsStatus {
+    Ok = 0,
+    InvalidData = 1,
+    NoFix = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GpsFix {
+    pub time_s: u32,
+    pub lat: i32,
+    pub lon: i32,
+    pub altitude: i16,
+    pub speed: u16,
+    pub heading: u16,
+    pub quality: GpsFixQuality,
+    pub satellites: u8,
+}
+
+impl GpsFix {
+    pub(crate) fn decode(data: &[u8]) -> Result<Self, GpsStatus> {
+        if data.len() != GPS_FIX_SIZE {
+            return Err(GpsStatus::InvalidData);
+        }
+        let mut r = WireReader::new(data);
+        let bad = |_| GpsStatus::InvalidData;
+        let fix = GpsFix {
+            time_s: r.u32().map_err(bad)?,
+            lat: r.u32().map_err(bad)? as i32,
+            lon: r.u32().map_err(bad)? as i32,
+            altitude: r.u16().map_err(bad)? as i16,
+            speed: r.u16().map_err(bad)?,
+            heading: r.u16().map_err(bad)?,
+            quality: GpsFixQuality::try_from(r.u8().map_err(bad)?).map_err(|_| GpsStatus::InvalidData)?,
+            satellites: r.u8().map_err(bad)?,
+        };
+        if fix.lat.unsigned_abs() > 900_000_000 || fix.lon.unsigned_abs() > 1_800_000_000 || fix.heading >= 36000 {
+            return Err(GpsStatus::InvalidData);
+        }
+        Ok(fix)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        use crate::communication::codec::WireWrite;
+        out.put_u32(self.time_s);
+        out.put_u32(self.lat as u32);
+        out.put_u32(self.lon as u32);
+        out.put_u16(self.altitude as u16);
+        out.put_u16(self.speed);
+        out.put_u16(self.heading);
+        out.put_u8(self.quality.into());
+        out.put_u8(self.satellites);
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.quality != GpsFixQuality::Invalid
+    }
+
+    pub fn lat_deg(&self) -> f64 {
+        self.lat as f64 / 1e7
+    }
+
+    pub fn lon_deg(&self) -> f64 {
+        self.lon as f64 / 1e7
+    }
+
+    pub fn speed_kmh(&self) -> f64 {
+        self.speed as f64 / 10.0
+    }
+
+    pub fn heading_deg(&self) -> f64 {
+        self.heading as f64 / 100.0
+    }
+}
+
+fn gps_latest() -> &'static RwLock<Option<GpsFix>> {
+    GPS_LATEST.get_or_init(|| RwLock::new(None))
+}
+
+/// 最近一次有效定位
+pub fn gps_latest_fix() -> Option<GpsFix> {
+    *gps_latest().read().ok()?
+}
+
+pub(crate) fn gps_update(fix: GpsFix) -> GpsStatus {
+    if !fix.is_valid() {
+        return GpsStatus::NoFix;
+    }
+    if let Ok(mut latest) = gps_latest().write() {
+        *latest = Some(fix);
+    }
+    GpsStatus::Ok
+}
+
+pub(crate) async fn gps_process(pack: McuComPackage, tx: &mpsc::Sender<Vec<u8>>) {
+    let Some(raw) = pack.as_raw() else {
+        return;
+    };
+    let status = match GpsFix::decode(raw) {
+        Ok(fix) => {
+            let status = gps_update(fix);
+            if status == GpsStatus::Ok {
+                let received = SystemTime::now();
+                // 写文件放到阻塞线程
+                tokio::task::spawn_blocking(move || gps_track_append(&fix, received));
+            }
+            status
+        }
+        Err(status) => {
+            println!("gps data invalid, len {}", raw.len());
+            status
+        }
+    };
+    let data = ComPackage::Raw(vec![status.into()]);
+    protocol_package_send(data, McuComMsgType::GpsDataResp, Some(&pack.head), tx).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fix(quality: GpsFixQuality) -> GpsFix {
+        GpsFix {
+            time_s: 1_760_000_000,
+            lat: 314_567_890,
+            lon: -1_213_456_780,
+            altitude: -12,
+            speed: 655,
+            heading: 27_050,
+            quality,
+            satellites: 9,
+        }
+    }
+
+    #[test]
+    fn fix_round_trips() {
+        let fix = fix(GpsFixQuality::Dgps);
+        let mut out = Vec::new();
+        fix.encode(&mut out);
+        assert_eq!(out.len(), GPS_FIX_SIZE);
+        let back = GpsFix::decode(&out).unwrap();
+        assert_eq!(back, fix);
+        assert!((back.lon_deg() + 121.345678).abs() < 1e-9);
+        assert_eq!(back.speed_kmh(), 65.5);
+        assert_eq!(back.heading_deg(), 270.5);
+    }
+
+    #[test]
+    fn bad_fix_is_rejected() {
+        let mut out = Vec::new();
+        fix(GpsFixQuality::Gps).encode(&mut out);
+        assert_eq!(GpsFix::decode(&out[..GPS_FIX_SIZE - 1]), Err(GpsStatus::InvalidData));
+        out[18] = 3;
+        assert_eq!(GpsFix::decode(&out), Err(GpsStatus::InvalidData));
+        assert_eq!(gps_update(fix(GpsFixQuality::Invalid)), GpsStatus::NoFix);
+    }
+}
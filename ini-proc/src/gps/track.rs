@@ -0,0 +1,132 @@
+//! GPS 轨迹日志
+//!
+//! Valid fixes are appended as CSV lines to `gps_track.csv` under the
+//! eMMC log dir. When the file reaches `TRACK_FILE_MAX` it is rotated to
+//! `gps_track.csv.1`, `.2`, ... keeping at most `TRACK_FILE_COUNT` old files.
+
+use super::fix::GpsFix;
+use crate::storage::emmc::emmc_get_log_path;
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+const TRACK_FILE_NAME: &str = "gps_track.csv";
+const TRACK_FILE_MAX: u64 = 1024 * 1024;
+const TRACK_FILE_COUNT: usize = 5;
+const TRACK_HEADER: &str = "recv_s,time_s,lat,lon,altitude_m,speed_kmh,heading_deg,quality,satellites\n";
+// 串行化写入和轮转
+static TRACK_LOCK: Mutex<()> = Mutex::new(());
+
+pub(crate) struct TrackLog {
+    dir: PathBuf,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl TrackLog {
+    pub(crate) fn new(dir: &Path, max_size: u64, max_files: usize) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            max_size,
+            max_files,
+        }
+    }
+
+    fn path(&self, index: usize) -> PathBuf {
+        match index {
+            0 => self.dir.join(TRACK_FILE_NAME),
+            n => self.dir.join(format!("{}.{}", TRACK_FILE_NAME, n)),
+        }
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        let _ = fs::remove_file(self.path(self.max_files));
+        for index in (0..self.max_files).rev() {
+            let from = self.path(index);
+            if from.exists() {
+                fs::rename(&from, self.path(index + 1))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn append(&self, fix: &GpsFix, received: SystemTime) -> io::Result<()> {
+        let path = self.path(0);
+        if fs::metadata(&path).is_ok_and(|m| m.len() >= self.max_size) {
+            self.rotate()?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(TRACK_HEADER.as_bytes())?;
+        }
+        let recv_s = received
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        writeln!(
+            file,
+            "{},{},{:.7},{:.7},{},{:.1},{:.2},{},{}",
+            recv_s,
+            fix.time_s,
+            fix.lat_deg(),
+            fix.lon_deg(),
+            fix.altitude,
+            fix.speed_kmh(),
+            fix.heading_deg(),
+            u8::from(fix.quality),
+            fix.satellites
+        )
+    }
+}
+
+/// 未挂载 eMMC 时不记录轨迹
+pub(crate) fn gps_track_append(fix: &GpsFix, received: SystemTime) {
+    let Some(dir) = emmc_get_log_path() else {
+        return;
+    };
+    let Ok(_guard) = TRACK_LOCK.lock() else {
+        return;
+    };
+    let log = TrackLog::new(Path::new(&dir), TRACK_FILE_MAX, TRACK_FILE_COUNT);
+    if let Err(e) = log.append(fix, received) {
+        println!("gps track append failed: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gps::fix::GpsFixQuality;
+
+    #[test]
+    fn track_log_rotates() {
+        let dir = std::env::temp_dir().join(format!("ini-proc-gps-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let fix = GpsFix {
+            time_s: 1_760_000_000,
+            lat: 314_567_890,
+            lon: 1_213_456_780,
+            altitude: 5,
+            speed: 0,
+            heading: 0,
+            quality: GpsFixQuality::Gps,
+            satellites: 7,
+        };
+        // 每个文件只放得下表头加一行
+        let log = TrackLog::new(&dir, TRACK_HEADER.len() as u64 + 1, 2);
+        for _ in 0..4 {
+            log.append(&fix, SystemTime::now()).unwrap();
+        }
+        let current = fs::read_to_string(log.path(0)).unwrap();
+        assert!(current.starts_with(TRACK_HEADER));
+        assert!(current.contains(",31.4567890,121.3456780,5,"));
+        assert!(log.path(1).exists());
+        assert!(log.path(2).exists());
+        assert!(!log.path(3).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,13 +1,18 @@
+mod command;
 mod common;
 mod communication;
 mod config;
+mod event;
+mod gps;
+mod media;
+mod ota;
 mod storage;
+mod system;
 use config::ini_parse::ini_init_config;
 use std::thread;
 use std::time::Duration;
 use storage::emmc::*;
-use communication::types::*;
-use communication::protocol::*;
+use communication::reliable::{reliable_init, ReliableConfig};
 const INI_FILENAME: &str = "mc6357.ini";
 
 #[tokio::main]
@@ -21,9 +26,19 @@ async fn main() {
     println!("emmc get event: {:?}", emmc_get_events_path());
     println!("emmc get recoder: {:?}", emmc_get_recoder_path(1));
     println!("emmc get info: {:?}", storage::emmc::emmc_get_info());
-
-    // let emmc_handle = emmc_check_start();
-
+    let ret = reliable_init(ReliableConfig::default());
+    println!("reliable init ret: {:?}", ret);
+    let emmc_handle = emmc_check_start();
+    let ret = command::builtin::command_init(emmc_handle);
+    println!("command init ret: {:?}", ret);
+    let ret = communication::peer_state::peer_state_ipc_init();
+    println!("peer state ipc init ret: {:?}", ret);
+    let ret = media::list::media_ipc_init();
+    println!("media ipc init ret: {:?}", ret);
+    let ret = system::gb28181::gb28181_watch_start();
+    println!("gb28181 watch start ret: {:?}", ret);
+    let ret = event::trigger::event_trigger_start();
+    println!("event trigger start ret: {:?}", ret);
 
     match communication::tcp_transport::tcp_server_start().await{
         Some(_) => {
@@ -34,8 +49,6 @@ async fn main() {
         }
     }
 
-
-    // emmc_check_stop(emmc_handle);
     loop {
         thread::sleep(Duration::from_secs(1));
     }
@@ -0,0 +1,136 @@
+//! 抓拍
+//!
+//! `CaptureBackend` grabs one JPEG from a channel (`video_device0..3`);
+//! `CommandCapture` does it with ffmpeg reading `/dev/videoN`, with the
+//! time OSD at the configured corner.
+
+use super::{media_events_dir, media_file_name, media_write_atomic, media_write_meta};
+use crate::storage::emmc::VIDEO_DEVICE_MAX_COUNT;
+use crate::system::osd::{osd_coordinate_get, osd_drawtext_filter};
+use crate::system::power::power_hold;
+use anyhow::{Context, Result, bail};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+pub trait CaptureBackend: Send + Sync {
+    /// Whether the channel has a camera attached.
+    fn available(&self, channel: u8) -> bool;
+    /// Writes one JPEG frame of `channel` to `dest`.
+    fn capture(&self, channel: u8, dest: &Path) -> Result<()>;
+}
+
+pub struct CommandCapture;
+
+fn video_device(channel: u8) -> String {
+    format!("/dev/video{}", channel)
+}
+
+impl CaptureBackend for CommandCapture {
+    fn available(&self, channel: u8) -> bool {
+        Path::new(&video_device(channel)).exists()
+    }
+
+    fn capture(&self, channel: u8, dest: &Path) -> Result<()> {
+        let status = Command::new("ffmpeg")
+            .args(["-loglevel", "error", "-f", "v4l2", "-i", &video_device(channel)])
+            .args(["-vf", &osd_drawtext_filter(osd_coordinate_get())])
+            .args(["-frames:v", "1", "-f", "mjpeg", "-y"])
+            .arg(dest)
+            .status()
+            .context("run ffmpeg failed")?;
+        if !status.success() {
+            bail!("ffmpeg exit: {}", status);
+        }
+        Ok(())
+    }
+}
+
+pub fn capture_channel_valid(channel: u8) -> bool {
+    (channel as usize) < VIDEO_DEVICE_MAX_COUNT
+}
+
+/// Takes a snapshot of `channel` into `dir` and returns its path.
+pub fn capture_snapshot(backend: &dyn CaptureBackend, dir: &Path, channel: u8) -> Result<PathBuf> {
+    if !capture_channel_valid(channel) {
+        bail!("channel {} out of range", channel);
+    }
+    // 休眠前等这一张写完
+    let _hold = power_hold().context("capture refused while sleeping")?;
+    let path = dir.join(media_file_name(channel, "jpg"));
+    media_write_atomic(&path, |part| backend.capture(channel, part))?;
+    media_write_meta(&path, channel);
+    println!("capture ch{} -> {:?}", channel, path);
+    Ok(path)
+}
+
+/// 事件抓拍: 所有接了摄像头的通道各拍一张
+pub fn capture_event_snapshots(backend: &dyn CaptureBackend) -> Vec<PathBuf> {
+    let dir = match media_events_dir() {
+        Ok(dir) => dir,
+        Err(err) => {
+            println!("event capture: no events dir: {}", err);
+            return Vec::new();
+        }
+    };
+    (0..VIDEO_DEVICE_MAX_COUNT as u8)
+        .filter(|&channel| backend.available(channel))
+        .filter_map(|channel| match capture_snapshot(backend, &dir, channel) {
+            Ok(path) => Some(path),
+            Err(err) => {
+                println!("event capture ch{} failed: {:?}", channel, err);
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::media::tests::media_test_dir;
+    use std::fs;
+
+    /// 把固定文件当作抓拍结果
+    pub(crate) struct FileCapture {
+        pub source: PathBuf,
+        pub channels: u8,
+    }
+
+    impl CaptureBackend for FileCapture {
+        fn available(&self, channel: u8) -> bool {
+            channel < self.channels
+        }
+        fn capture(&self, channel: u8, dest: &Path) -> Result<()> {
+            if !self.available(channel) {
+                bail!("no camera on ch{}", channel);
+            }
+            fs::copy(&self.source, dest)?;
+            Ok(())
+        }
+    }
+
+    pub(crate) fn file_capture(channels: u8) -> FileCapture {
+        let source = std::env::temp_dir().join(format!("ini-proc-capture-{}.jpg", std::process::id()));
+        fs::write(&source, [0xff, 0xd8, 0xff, 0xd9]).unwrap();
+        FileCapture { source, channels }
+    }
+
+    #[test]
+    fn snapshot_is_named_by_time_and_channel() {
+        let backend = file_capture(2);
+        let dir = media_test_dir("capture");
+        let path = capture_snapshot(&backend, &dir, 1).unwrap();
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert!(name.ends_with("_ch1.jpg"), "{}", name);
+        assert_eq!(fs::read(&path).unwrap(), [0xff, 0xd8, 0xff, 0xd9]);
+        assert!(!super::super::media_part_path(&path).exists());
+        let meta = path.with_file_name(format!("{}.meta", name));
+        assert!(fs::read_to_string(&meta).unwrap().starts_with("channel=1\n"));
+
+        assert!(capture_snapshot(&backend, &dir, 3).is_err());
+        assert!(capture_snapshot(&backend, &dir, 4).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
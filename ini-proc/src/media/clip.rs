@@ -0,0 +1,242 @@
+//! 事件录像
+//!
+//! A clip is the pre-event part, copied from the continuous recording
+//! segments of the channel (`record/video_deviceN`), followed by
+//! `duration` of live video from a `ClipBackend`. Both are raw Annex-B
+//! elementary streams, so they are joined by concatenation. Only closed
+//! segments lying wholly within `pre` are copied, so the pre-event part
+//! is at most `pre` long; the segment still being written and segments
+//! in another codec than the backend's are skipped.
+//! `CommandClip` burns in the same time OSD as snapshots. When deep sleep
+//! is requested the live part ends early and the clip is completed with
+//! what was recorded so far.
+
+use super::{media_file_name, media_write_atomic, media_write_meta};
+use crate::storage::emmc::{VIDEO_DEVICE_MAX_COUNT, emmc_get_recoder_path};
+use crate::system::osd::{osd_coordinate_get, osd_drawtext_filter};
+use crate::system::power::power_hold;
+use anyhow::{Context, Result, bail};
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::atomic::{AtomicU8, Ordering},
+    time::{Duration, SystemTime},
+};
+
+pub const CLIP_DURATION_MAX: Duration = Duration::from_secs(300);
+pub const CLIP_PRE_MAX: Duration = Duration::from_secs(60);
+/// 最近这么久内有写入的分段视为仍在录制
+const CLIP_SEGMENT_IDLE: Duration = Duration::from_secs(1);
+/// 每通道一位，录制中置位
+static CLIP_RUNNING: AtomicU8 = AtomicU8::new(0);
+
+pub trait ClipBackend: Send + Sync {
+    fn available(&self, channel: u8) -> bool;
+    /// File extension of the stream `record` produces, "h264" or "h265".
+    fn codec(&self) -> &'static str;
+    /// Writes `duration` of live video from `channel` to `out`, or less
+    /// once `stop` returns true.
+    fn record(&self, channel: u8, duration: Duration, out: &mut dyn Write, stop: &dyn Fn() -> bool) -> Result<()>;
+}
+
+pub struct CommandClip;
+
+impl ClipBackend for CommandClip {
+    fn available(&self, channel: u8) -> bool {
+        Path::new(&format!("/dev/video{}", channel)).exists()
+    }
+
+    fn codec(&self) -> &'static str {
+        "h264"
+    }
+
+    fn record(&self, channel: u8, duration: Duration, out: &mut dyn Write, stop: &dyn Fn() -> bool) -> Result<()> {
+        let mut child = Command::new("ffmpeg")
+            .args(["-loglevel", "error", "-f", "v4l2", "-i", &format!("/dev/video{}", channel)])
+            .args(["-vf", &osd_drawtext_filter(osd_coordinate_get())])
+            .args(["-t", &duration.as_secs().to_string(), "-c:v", "libx264", "-f", "h264", "pipe:1"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("run ffmpeg failed")?;
+        let mut stdin = child.stdin.take();
+        let mut stdout = child.stdout.take().context("no ffmpeg stdout")?;
+        let mut buf = [0u8; 64 * 1024];
+        loop {
+            // 'q' 让 ffmpeg 正常结束并输出剩余数据
+            if stop() && let Some(mut stdin) = stdin.take() {
+                println!("clip ch{} stopped for sleep", channel);
+                let _ = stdin.write_all(b"q");
+            }
+            let n = stdout.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            out.write_all(&buf[..n])?;
+        }
+        drop(stdin);
+        let status = child.wait()?;
+        if !status.success() {
+            bail!("ffmpeg exit: {}", status);
+        }
+        Ok(())
+    }
+}
+
+/// Closed continuous recording segments of `dir` with extension `codec`
+/// that lie wholly within `pre` before `now`, oldest first. A segment
+/// spans from the previous one's last write to its own; the oldest one's
+/// start is unknown, so it is never taken.
+pub(crate) fn clip_pre_segments(dir: &Path, codec: &str, pre: Duration, now: SystemTime) -> Vec<PathBuf> {
+    if pre.is_zero() {
+        return Vec::new();
+    }
+    let since = now - pre;
+    let closed = now - CLIP_SEGMENT_IDLE;
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut segments: Vec<(SystemTime, PathBuf)> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().and_then(|e| e.to_str()) == Some(codec))
+        .filter_map(|p| Some((fs::metadata(&p).ok()?.modified().ok()?, p)))
+        .collect();
+    segments.sort();
+    segments
+        .windows(2)
+        .filter(|w| w[0].0 >= since && w[1].0 <= closed)
+        .map(|w| w[1].1.clone())
+        .collect()
+}
+
+struct ClipGuard(u8);
+
+impl ClipGuard {
+    fn acquire(channel: u8) -> Option<Self> {
+        let bit = 1 << channel;
+        let prev = CLIP_RUNNING.fetch_or(bit, Ordering::SeqCst);
+        (prev & bit == 0).then_some(Self(bit))
+    }
+}
+
+impl Drop for ClipGuard {
+    fn drop(&mut self) {
+        CLIP_RUNNING.fetch_and(!self.0, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ClipError {
+    InvalidParam,
+    Busy,
+    Failed,
+}
+
+/// Records a clip of `channel` into `dir` and returns its path once it
+/// is complete. One clip per channel at a time.
+pub fn clip_record(
+    backend: &dyn ClipBackend,
+    dir: &Path,
+    channel: u8,
+    pre: Duration,
+    duration: Duration,
+) -> Result<PathBuf, ClipError> {
+    if channel as usize >= VIDEO_DEVICE_MAX_COUNT || duration.is_zero() || duration > CLIP_DURATION_MAX {
+        return Err(ClipError::InvalidParam);
+    }
+    let _guard = ClipGuard::acquire(channel).ok_or(ClipError::Busy)?;
+    clip_write(backend, dir, channel, pre.min(CLIP_PRE_MAX), duration).map_err(|err| {
+        println!("clip ch{} failed: {:?}", channel, err);
+        ClipError::Failed
+    })
+}
+
+fn clip_write(backend: &dyn ClipBackend, dir: &Path, channel: u8, pre: Duration, duration: Duration) -> Result<PathBuf> {
+    let hold = power_hold().context("recording refused while sleeping")?;
+    let codec = backend.codec();
+    let segments = emmc_get_recoder_path(channel as usize)
+        .map(|dir| clip_pre_segments(Path::new(&dir), codec, pre, SystemTime::now()))
+        .unwrap_or_default();
+    let path = dir.join(media_file_name(channel, codec));
+    media_write_atomic(&path, |part| {
+        let mut out = File::create(part)?;
+        for segment in &segments {
+            io::copy(&mut File::open(segment)?, &mut out).with_context(|| format!("copy {:?} failed", segment))?;
+        }
+        if !hold.sleep_requested() {
+            backend.record(channel, duration, &mut out, &|| hold.sleep_requested())?;
+        }
+        out.sync_all()?;
+        Ok(())
+    })?;
+    media_write_meta(&path, channel);
+    println!("clip ch{} -> {:?}, {} pre segments", channel, path, segments.len());
+    Ok(path)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::media::tests::media_test_dir;
+
+    /// 录制内容为固定字节
+    pub(crate) struct FakeClip;
+
+    impl ClipBackend for FakeClip {
+        fn available(&self, _channel: u8) -> bool {
+            true
+        }
+        fn codec(&self) -> &'static str {
+            "h264"
+        }
+        fn record(&self, _channel: u8, _duration: Duration, out: &mut dyn Write, _stop: &dyn Fn() -> bool) -> Result<()> {
+            out.write_all(b"live")?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn pre_segments_are_closed_and_within_pre() {
+        let dir = media_test_dir("segments");
+        let now = SystemTime::now();
+        // 分段 b 从 a 的最后写入开始，依此类推; e 仍在录制
+        for (name, age) in [("a.h264", 30), ("b.h264", 20), ("c.h264", 10), ("x.h265", 5), ("d.h264", 2), ("e.h264", 0)] {
+            let path = dir.join(name);
+            fs::write(&path, name).unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(now - Duration::from_secs(age))
+                .unwrap();
+        }
+
+        let segments = clip_pre_segments(&dir, "h264", Duration::from_secs(12), now);
+        assert_eq!(segments, [dir.join("d.h264")]);
+        let segments = clip_pre_segments(&dir, "h264", Duration::from_secs(20), now);
+        assert_eq!(segments, [dir.join("c.h264"), dir.join("d.h264")]);
+        let segments = clip_pre_segments(&dir, "h264", Duration::from_secs(60), now);
+        assert_eq!(segments, [dir.join("b.h264"), dir.join("c.h264"), dir.join("d.h264")]);
+        assert!(clip_pre_segments(&dir, "h264", Duration::ZERO, now).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn clip_is_written_once_per_channel() {
+        let dir = media_test_dir("clip-events");
+        let path = clip_record(&FakeClip, &dir, 2, Duration::ZERO, Duration::from_secs(1)).unwrap();
+        assert!(path.to_str().unwrap().ends_with("_ch2.h264"));
+        assert_eq!(fs::read(&path).unwrap(), b"live");
+
+        let _running = ClipGuard::acquire(3).unwrap();
+        assert_eq!(clip_record(&FakeClip, &dir, 3, Duration::ZERO, Duration::from_secs(1)), Err(ClipError::Busy));
+        assert_eq!(
+            clip_record(&FakeClip, &dir, 1, Duration::ZERO, CLIP_DURATION_MAX + Duration::from_secs(1)),
+            Err(ClipError::InvalidParam)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+//! 事件文件列表
+//!
+//! IPC sub-type `MEDIA_IPC_LIST` lists the media files waiting in the
+//! events directory, oldest first, so the MCU can ask for them by name
+//! with RetransmissionDocument. Request: optional `offset u32` (tag 1),
+//! or `after` (tag 2) to continue after the last name already received.
+//! Reply: `total u32` (tag 1), then one `name` (tag 2) per file from
+//! `offset` on, as many as fit one frame.
+//!
+//! New event files are announced to every connected MCU with
+//! `MEDIA_IPC_NEW`: one `name` (tag 2) per file.
+
+use super::media_events_dir;
+use crate::communication::ipc::{IpcMessage, IpcStatus, Tlv, ipc_register, ipc_send};
+use crate::communication::tcp_transport::tcp_server_sessions;
+use crate::communication::types::COM_PACKAGE_MAX_SIZE;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub const MEDIA_IPC_LIST: u16 = 0x0101;
+pub const MEDIA_IPC_NEW: u16 = 0x0102;
+const TAG_OFFSET: u8 = 1;
+const TAG_AFTER: u8 = 2;
+const TAG_TOTAL: u8 = 1;
+const TAG_NAME: u8 = 2;
+/// sub_type + status，再加 total 的 TLV
+const REPLY_FIXED_SIZE: usize = 3 + 3 + 4;
+
+fn media_is_event_file(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| matches!(ext, "h264" | "h265" | "jpg" | "jpeg"))
+}
+
+/// 文件名以时间开头，按名字排序即按时间
+pub(crate) fn media_list_names(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+        .filter_map(|e| e.file_name().into_string().ok())
+        .filter(|name| media_is_event_file(name))
+        .collect();
+    names.sort();
+    names
+}
+
+pub(crate) fn media_list_reply(names: &[String], offset: usize) -> Vec<Tlv> {
+    let mut items = vec![Tlv::from_u32(TAG_TOTAL, names.len() as u32)];
+    let mut size = REPLY_FIXED_SIZE;
+    for name in names.iter().skip(offset) {
+        size += 3 + name.len();
+        if size > COM_PACKAGE_MAX_SIZE {
+            break;
+        }
+        items.push(Tlv::from_str(TAG_NAME, name));
+    }
+    items
+}
+
+fn media_list_handle(msg: &IpcMessage) -> Result<Vec<Tlv>, IpcStatus> {
+    let dir = media_events_dir().map_err(|_| IpcStatus::Failed)?;
+    let names = media_list_names(&dir);
+    // 按名字续传，不受期间删除的文件影响
+    let offset = match (msg.get(TAG_AFTER), msg.get(TAG_OFFSET)) {
+        (Some(tlv), _) => {
+            let after = tlv.as_str().ok_or(IpcStatus::InvalidData)?;
+            names.partition_point(|name| name.as_str() <= after)
+        }
+        (None, Some(tlv)) => tlv.as_u32().ok_or(IpcStatus::InvalidData)? as usize,
+        (None, None) => 0,
+    };
+    Ok(media_list_reply(&names, offset))
+}
+
+pub(crate) fn media_new_message(paths: &[PathBuf]) -> IpcMessage {
+    let items = paths
+        .iter()
+        .filter_map(|p| p.file_name()?.to_str())
+        .map(|name| Tlv::from_str(TAG_NAME, name))
+        .collect();
+    IpcMessage::new(MEDIA_IPC_NEW, items)
+}
+
+/// 通知所有在线的 MCU 有新的事件文件
+pub async fn media_ipc_notify(paths: &[PathBuf]) {
+    if paths.is_empty() {
+        return;
+    }
+    let msg = media_new_message(paths);
+    for tx in tcp_server_sessions().await {
+        if let Err(err) = ipc_send(&msg, &tx).await {
+            println!("media new notify failed: {}", err);
+        }
+    }
+}
+
+pub fn media_ipc_init() -> Option<i32> {
+    ipc_register(MEDIA_IPC_LIST, Box::new(media_list_handle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_is_sorted_and_paged() {
+        let dir = std::env::temp_dir().join(format!("ini-proc-list-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        for name in ["20250102_ch0.jpg", "20250101_ch1.h264", "20250103_ch0.jpg.meta", "x.jpg.part"] {
+            fs::write(dir.join(name), b"x").unwrap();
+        }
+        let names = media_list_names(&dir);
+        assert_eq!(names, ["20250101_ch1.h264", "20250102_ch0.jpg"]);
+
+        let items = media_list_reply(&names, 1);
+        assert_eq!(items[0].as_u32(), Some(2));
+        assert_eq!(items[1..], [Tlv::from_str(TAG_NAME, "20250102_ch0.jpg")]);
+
+        let many: Vec<String> = (0..100).map(|i| format!("{:026}.jpg", i)).collect();
+        let items = media_list_reply(&many, 0);
+        let size: usize = REPLY_FIXED_SIZE + items[1..].iter().map(|t| 3 + t.value.len()).sum::<usize>();
+        assert!(items.len() < 101 && size <= COM_PACKAGE_MAX_SIZE);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn new_message_carries_file_names() {
+        let msg = media_new_message(&[dir_file("20250101_ch0.jpg"), dir_file("20250101_ch1.h264")]);
+        let back = IpcMessage::decode(&msg.encode()).unwrap();
+        assert_eq!(back.sub_type, MEDIA_IPC_NEW);
+        let names: Vec<&str> = back.items.iter().filter_map(Tlv::as_str).collect();
+        assert_eq!(names, ["20250101_ch0.jpg", "20250101_ch1.h264"]);
+    }
+
+    fn dir_file(name: &str) -> PathBuf {
+        Path::new("/data/events").join(name)
+    }
+}
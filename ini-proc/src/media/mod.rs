@@ -0,0 +1,116 @@
+//! 抓拍与事件录像
+//!
+//! Event media (snapshots and clips) is written to the events directory
+//! (`emmc_get_events_path`, `/tmp/events` while the emmc is not usable)
+//! and named after the time and channel, e.g.
+//! `20250101_120000_123_ch0.jpg`. Files are written
+//! under a `.part` name and renamed when complete, so a partial file is
+//! never counted in `remain_file` or offered for upload. Each file gets a
+//! `<name>.meta` sidecar with the channel, the latest GPS fix and the
+//! lock state from the last MCU heartbeat.
+
+pub mod capture;
+pub mod clip;
+pub mod list;
+
+use crate::communication::peer_state::peer_state_latest;
+use crate::gps::fix::{GpsFix, gps_latest_fix};
+use crate::storage::emmc;
+use chrono::Local;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+const TMP_EVENTS_DIR: &str = "/tmp/events";
+
+pub fn media_events_dir() -> io::Result<PathBuf> {
+    let dir = emmc::emmc_get_events_path().unwrap_or_else(|| TMP_EVENTS_DIR.to_string());
+    emmc::safe_mkdir(Path::new(&dir))?;
+    Ok(PathBuf::from(dir))
+}
+
+pub fn media_file_name(channel: u8, ext: &str) -> String {
+    format!("{}_ch{}.{}", Local::now().format("%Y%m%d_%H%M%S_%3f"), channel, ext)
+}
+
+pub(crate) fn media_part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// Runs `write` on the `.part` file, then moves it into place.
+pub(crate) fn media_write_atomic(path: &Path, write: impl FnOnce(&Path) -> anyhow::Result<()>) -> anyhow::Result<()> {
+    let part = media_part_path(path);
+    let ret = write(&part).and_then(|_| Ok(fs::rename(&part, path)?));
+    if ret.is_err() {
+        let _ = fs::remove_file(&part);
+    }
+    ret
+}
+
+pub(crate) fn media_meta(channel: u8, fix: Option<&GpsFix>) -> String {
+    let mut meta = format!("channel={}\n", channel);
+    match fix {
+        Some(fix) => meta.push_str(&format!(
+            "gps_time={}\nlat={:.7}\nlon={:.7}\naltitude={}\nspeed_kmh={:.1}\nheading={:.2}\nsatellites={}\n",
+            fix.time_s,
+            fix.lat_deg(),
+            fix.lon_deg(),
+            fix.altitude,
+            fix.speed_kmh(),
+            fix.heading_deg(),
+            fix.satellites
+        )),
+        None => meta.push_str("gps=none\n"),
+    }
+    meta
+}
+
+/// 在媒体文件旁写入元数据，失败只记录日志
+pub(crate) fn media_write_meta(path: &Path, channel: u8) {
+    let mut meta_path = path.as_os_str().to_owned();
+    meta_path.push(".meta");
+    let mut meta = media_meta(channel, gps_latest_fix().as_ref());
+    if let Some(peer) = peer_state_latest() {
+        let lock = if peer.is_locked() { "locked" } else { "unlocked" };
+        meta.push_str(&format!("mcu_id={}\nlock={}\n", peer.mcu_id, lock));
+    }
+    if let Err(err) = media_write_atomic(Path::new(&meta_path), |part| Ok(fs::write(part, meta)?)) {
+        println!("write meta {:?} failed: {:?}", meta_path, err);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::gps::fix::GpsFixQuality;
+
+    /// 测试用的空目录，代替真实的事件目录
+    pub(crate) fn media_test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ini-proc-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn meta_carries_location() {
+        let fix = GpsFix {
+            time_s: 1_700_000_000,
+            lat: 225_431_234,
+            lon: 1_139_876_543,
+            altitude: 12,
+            speed: 456,
+            heading: 9000,
+            quality: GpsFixQuality::Gps,
+            satellites: 9,
+        };
+        let meta = media_meta(1, Some(&fix));
+        assert!(meta.starts_with("channel=1\n"));
+        assert!(meta.contains("lat=22.5431234\nlon=113.9876543\n"));
+        assert!(meta.contains("speed_kmh=45.6\nheading=90.00\n"));
+        assert_eq!(media_meta(0, None), "channel=0\ngps=none\n");
+    }
+}
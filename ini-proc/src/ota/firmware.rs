@@ -0,0 +1,728 @@
+//! 固件升级
+//!
+//! The MCU streams the image with OtaData messages:
+//!
+//! | op u8 | Begin: size u32, crc32 u32, version utf8 |
+//! |       | Chunk: offset u32, data ...              |
+//! |       | End / Abort: no body                     |
+//!
+//! Every message is answered with OtaDataResp `| op u8 | status u8 |
+//! progress u8 | next_offset u32 |`. The image is staged under the eMMC
+//! mount, verified on End, then written to the inactive slot; the final
+//! result is reported with a Cmd carrying `CmdType::OtaResp`.
+//!
+//! The slot devices (`ota/slot_a`, `ota/slot_b`) have no defaults: a
+//! board without them configured refuses Begin with `NotConfigured`. The
+//! slot to boot is switched in the u-boot environment.
+
+use crate::command::payload::{CmdPayload, CmdStatus, OtaResp};
+use crate::communication::codec::{WireReader, WireWrite};
+use crate::communication::protocol::*;
+use crate::communication::reliable::reliable_request;
+use crate::communication::types::*;
+use crate::config::ini_parse;
+use crate::storage::emmc::emmc_get_ota_path;
+use anyhow::{Context, Result, anyhow};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Mutex, OnceLock},
+};
+use tokio::sync::mpsc;
+
+const OTA_IMAGE_NAME: &str = "firmware.img.part";
+/// u-boot 环境变量，值为 a 或 b
+const BOOT_SLOT_VAR: &str = "boot_slot";
+const OTA_IMAGE_MAX: u32 = 64 * 1024 * 1024;
+static OTA: OnceLock<Mutex<OtaUpdater>> = OnceLock::new();
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive)]
+pub enum OtaOp {
+    Begin = 1,
+    Chunk = 2,
+    End = 3,
+    Abort = 4,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, IntoPrimitive)]
+pub enum OtaStatus {
+    Ok = 0,
+    Installing = 1,
+    Done = 2,
+    InvalidRequest = 3,
+    NotStarted = 4,
+    Busy = 5,
+    OffsetMismatch = 6,
+    SizeMismatch = 7,
+    ChecksumMismatch = 8,
+    StorageError = 9,
+    InstallFailed = 10,
+    Aborted = 11,
+    NotConfigured = 12,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    fn other(self) -> Slot {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+    fn name(self) -> &'static str {
+        match self {
+            Slot::A => "a",
+            Slot::B => "b",
+        }
+    }
+    fn from_name(name: &str) -> Option<Slot> {
+        match name {
+            "a" => Some(Slot::A),
+            "b" => Some(Slot::B),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum OtaRequest {
+    Begin {
+        size: u32,
+        crc32: u32,
+        version: String,
+    },
+    Chunk {
+        offset: u32,
+        data: Vec<u8>,
+    },
+    End,
+    Abort,
+}
+
+impl OtaRequest {
+    pub(crate) fn decode(data: &[u8]) -> Result<Self, OtaStatus> {
+        let mut r = WireReader::new(data);
+        let op = r.u8().map_err(|_| OtaStatus::InvalidRequest)?;
+        let bad = |_| OtaStatus::InvalidRequest;
+        match OtaOp::try_from(op).map_err(|_| OtaStatus::InvalidRequest)? {
+            OtaOp::Begin => Ok(OtaRequest::Begin {
+                size: r.u32().map_err(bad)?,
+                crc32: r.u32().map_err(bad)?,
+                version: String::from_utf8_lossy(r.rest()).trim_end_matches('\0').to_string(),
+            }),
+            OtaOp::Chunk => Ok(OtaRequest::Chunk {
+                offset: r.u32().map_err(bad)?,
+                data: r.rest().to_vec(),
+            }),
+            OtaOp::End => Ok(OtaRequest::End),
+            OtaOp::Abort => Ok(OtaRequest::Abort),
+        }
+    }
+    pub(crate) fn op(&self) -> OtaOp {
+        match self {
+            OtaRequest::Begin { .. } => OtaOp::Begin,
+            OtaRequest::Chunk { .. } => OtaOp::Chunk,
+            OtaRequest::End => OtaOp::End,
+            OtaRequest::Abort => OtaOp::Abort,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct OtaReply {
+    pub op: u8,
+    pub status: OtaStatus,
+    pub progress: u8,
+    pub next_offset: u32,
+}
+
+impl OtaReply {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(7);
+        out.put_u8(self.op);
+        out.put_u8(self.status.into());
+        out.put_u8(self.progress);
+        out.put_u32(self.next_offset);
+        out
+    }
+}
+
+/// IEEE crc32, the same one zlib and `crc32` tooling use.
+pub(crate) fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn crc32_file(path: &Path, len: u64) -> io::Result<u32> {
+    let mut file = File::open(path)?.take(len);
+    let mut buf = [0u8; 4096];
+    let mut crc = 0;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(crc);
+        }
+        crc = crc32_update(crc, &buf[..n]);
+    }
+}
+
+pub(crate) struct OtaSession {
+    path: PathBuf,
+    file: File,
+    size: u32,
+    crc32: u32,
+    version: String,
+    received: u32,
+}
+
+impl OtaSession {
+    fn progress(&self) -> u8 {
+        if self.size == 0 {
+            return 0;
+        }
+        (self.received as u64 * 100 / self.size as u64) as u8
+    }
+}
+
+/// Writes a verified image into a slot and records it as the next boot
+/// slot. Swappable so tests and boards with other layouts can plug in.
+pub(crate) trait SlotInstaller: Send + Sync {
+    fn install(&self, image: &Path, len: u64, crc32: u32) -> Result<Slot>;
+}
+
+/// Where the bootloader learns which slot to boot.
+pub(crate) trait BootControl: Send + Sync {
+    fn active_slot(&self) -> Result<Slot>;
+    fn set_boot_slot(&self, slot: Slot) -> Result<()>;
+}
+
+/// u-boot 环境变量，经 fw_printenv/fw_setenv 读写
+pub(crate) struct UbootEnv;
+
+impl UbootEnv {
+    fn read(&self) -> Result<String> {
+        let out = Command::new("fw_printenv")
+            .args(["-n", BOOT_SLOT_VAR])
+            .output()
+            .context("run fw_printenv failed")?;
+        if !out.status.success() {
+            return Err(anyhow!("{} not set in u-boot env", BOOT_SLOT_VAR));
+        }
+        Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+    }
+}
+
+impl BootControl for UbootEnv {
+    fn active_slot(&self) -> Result<Slot> {
+        let value = self.read()?;
+        Slot::from_name(&value).ok_or_else(|| anyhow!("bad {}: {:?}", BOOT_SLOT_VAR, value))
+    }
+
+    fn set_boot_slot(&self, slot: Slot) -> Result<()> {
+        let status = Command::new("fw_setenv")
+            .args([BOOT_SLOT_VAR, slot.name()])
+            .status()
+            .context("run fw_setenv failed")?;
+        if !status.success() {
+            return Err(anyhow!("fw_setenv exit: {}", status));
+        }
+        // 读回确认确实写进了环境分区
+        if self.active_slot()? != slot {
+            return Err(anyhow!("{} read back mismatch", BOOT_SLOT_VAR));
+        }
+        Ok(())
+    }
+}
+
+/// Slot devices as configured in the INI; both must be set and differ.
+pub(crate) fn ota_slot_paths() -> Result<(PathBuf, PathBuf), OtaStatus> {
+    let slot = |key| {
+        ini_parse::ini_get_ini_config("ota", key)
+            .filter(|v| !v.trim().is_empty())
+            .map(PathBuf::from)
+    };
+    match (slot("slot_a"), slot("slot_b")) {
+        (Some(a), Some(b)) if a != b => Ok((a, b)),
+        _ => Err(OtaStatus::NotConfigured),
+    }
+}
+
+/// Slots are existing paths (block devices on the target, files in
+/// tests); they are never created.
+pub(crate) struct FileSlotInstaller {
+    slot_a: PathBuf,
+    slot_b: PathBuf,
+    boot: Box<dyn BootControl>,
+}
+
+impl FileSlotInstaller {
+    pub(crate) fn new(slot_a: PathBuf, slot_b: PathBuf, boot: Box<dyn BootControl>) -> Self {
+        Self { slot_a, slot_b, boot }
+    }
+    pub(crate) fn from_config() -> Result<Self, OtaStatus> {
+        let (slot_a, slot_b) = ota_slot_paths()?;
+        Ok(Self::new(slot_a, slot_b, Box::new(UbootEnv)))
+    }
+    fn slot_path(&self, slot: Slot) -> &Path {
+        match slot {
+            Slot::A => &self.slot_a,
+            Slot::B => &self.slot_b,
+        }
+    }
+}
+
+impl SlotInstaller for FileSlotInstaller {
+    fn install(&self, image: &Path, len: u64, crc32: u32) -> Result<Slot> {
+        let slot = self.boot.active_slot().context("read active slot failed")?.other();
+        let target = self.slot_path(slot);
+        println!("ota install {:?} -> slot {} {:?}", image, slot.name(), target);
+        {
+            let mut src = File::open(image).context("open staged image failed")?;
+            let mut dst = OpenOptions::new()
+                .write(true)
+                .open(target)
+                .with_context(|| format!("open slot {:?} failed", target))?;
+            io::copy(&mut src, &mut dst).context("write slot failed")?;
+            dst.sync_all().context("sync slot failed")?;
+        }
+        if crc32_file(target, len).context("read back slot failed")? != crc32 {
+            return Err(anyhow!("slot {} read back checksum mismatch", slot.name()));
+        }
+        self.boot.set_boot_slot(slot).context("switch boot slot failed")?;
+        Ok(slot)
+    }
+}
+
+pub(crate) struct OtaUpdater {
+    staging_dir: Option<PathBuf>,
+    session: Option<OtaSession>,
+    installing: bool,
+    last_status: OtaStatus,
+}
+
+impl OtaUpdater {
+    /// `staging_dir` None stages under the eMMC mount at Begin time.
+    pub(crate) fn new(staging_dir: Option<PathBuf>) -> Self {
+        Self {
+            staging_dir,
+            session: None,
+            installing: false,
+            last_status: OtaStatus::NotStarted,
+        }
+    }
+
+    fn reply(&self, op: OtaOp, status: OtaStatus) -> OtaReply {
+        OtaReply {
+            op: op.into(),
+            status,
+            progress: self.session.as_ref().map_or(0, |s| s.progress()),
+            next_offset: self.session.as_ref().map_or(0, |s| s.received),
+        }
+    }
+
+    pub(crate) fn status(&self) -> (OtaStatus, u8) {
+        let progress = self.session.as_ref().map_or(0, |s| s.progress());
+        if self.installing {
+            (OtaStatus::Installing, 100)
+        } else if self.session.is_some() {
+            (OtaStatus::Ok, progress)
+        } else {
+            (self.last_status, 0)
+        }
+    }
+
+    fn begin(&mut self, size: u32, crc32: u32, version: String) -> Result<(), OtaStatus> {
+        if size == 0 || size > OTA_IMAGE_MAX {
+            return Err(OtaStatus::SizeMismatch);
+        }
+        // 同一镜像重新 Begin 视为断点续传
+        if let Some(s) = &self.session
+            && s.size == size
+            && s.crc32 == crc32
+            && s.version == version
+        {
+            println!("ota resume {} at {}", version, s.received);
+            return Ok(());
+        }
+        self.abort();
+        let dir = match &self.staging_dir {
+            Some(dir) => dir.clone(),
+            None => PathBuf::from(emmc_get_ota_path().ok_or(OtaStatus::StorageError)?),
+        };
+        fs::create_dir_all(&dir).map_err(|_| OtaStatus::StorageError)?;
+        let path = dir.join(OTA_IMAGE_NAME);
+        let file = File::create(&path).map_err(|_| OtaStatus::StorageError)?;
+        println!("ota begin {} size {} crc32 {:#010x}", version, size, crc32);
+        self.session = Some(OtaSession {
+            path,
+            file,
+            size,
+            crc32,
+            version,
+            received: 0,
+        });
+        Ok(())
+    }
+
+    fn chunk(&mut self, offset: u32, data: &[u8]) -> Result<(), OtaStatus> {
+        let s = self.session.as_mut().ok_or(OtaStatus::NotStarted)?;
+        let end = offset as u64 + data.len() as u64;
+        if end > s.size as u64 {
+            return Err(OtaStatus::SizeMismatch);
+        }
+        if end <= s.received as u64 {
+            // 重传的旧分块
+            return Ok(());
+        }
+        if offset != s.received {
+            return Err(OtaStatus::OffsetMismatch);
+        }
+        s.file
+            .seek(SeekFrom::Start(offset as u64))
+            .and_then(|_| s.file.write_all(data))
+            .map_err(|_| OtaStatus::StorageError)?;
+        s.received = end as u32;
+        Ok(())
+    }
+
+    pub(crate) fn abort(&mut self) {
+        if let Some(s) = self.session.take() {
+            println!("ota abort {}", s.version);
+            let _ = fs::remove_file(&s.path);
+            self.last_status = OtaStatus::Aborted;
+        }
+    }
+
+    /// Handles Begin, Chunk and Abort. End goes through `take_for_install`.
+    pub(crate) fn handle(&mut self, req: OtaRequest) -> OtaReply {
+        let op = req.op();
+        if self.installing {
+            return self.reply(op, OtaStatus::Busy);
+        }
+        let ret = match req {
+            OtaRequest::Begin {
+                size,
+                crc32,
+                version,
+            } => self.begin(size, crc32, version),
+            OtaRequest::Chunk { offset, data } => self.chunk(offset, &data),
+            OtaRequest::Abort => {
+                self.abort();
+                Ok(())
+            }
+            OtaRequest::End => Err(OtaStatus::InvalidRequest),
+        };
+        self.reply(op, ret.err().unwrap_or(OtaStatus::Ok))
+    }
+
+    /// Hands the finished session over for verification and install.
+    pub(crate) fn take_for_install(&mut self) -> Result<OtaSession, OtaStatus> {
+        if self.installing {
+            return Err(OtaStatus::Busy);
+        }
+        let s = self.session.take().ok_or(OtaStatus::NotStarted)?;
+        if s.received != s.size {
+            let status = OtaStatus::SizeMismatch;
+            self.session = Some(s);
+            return Err(status);
+        }
+        self.installing = true;
+        Ok(s)
+    }
+
+    pub(crate) fn complete(&mut self, status: OtaStatus) {
+        self.installing = false;
+        self.last_status = status;
+    }
+}
+
+/// Verifies the staged image and installs it. Blocking.
+pub(crate) fn ota_install(session: OtaSession, installer: &dyn SlotInstaller) -> Result<Slot, OtaStatus> {
+    let OtaSession {
+        path,
+        file,
+        size,
+        crc32,
+        version,
+        ..
+    } = session;
+    file.sync_all().map_err(|_| OtaStatus::StorageError)?;
+    drop(file);
+    let ret = match crc32_file(&path, size as u64) {
+        Ok(crc) if crc == crc32 => installer.install(&path, size as u64, crc32).map_err(|e| {
+            eprintln!("ota install {} failed: {:?}", version, e);
+            OtaStatus::InstallFailed
+        }),
+        Ok(crc) => {
+            eprintln!("ota crc32 {:#010x} != {:#010x}", crc, crc32);
+            Err(OtaStatus::ChecksumMismatch)
+        }
+        Err(_) => Err(OtaStatus::StorageError),
+    };
+    let _ = fs::remove_file(&path);
+    ret
+}
+
+fn ota_updater() -> &'static Mutex<OtaUpdater> {
+    OTA.get_or_init(|| Mutex::new(OtaUpdater::new(None)))
+}
+
+pub(crate) fn ota_status() -> (OtaStatus, u8) {
+    ota_updater()
+        .lock()
+        .map(|u| u.status())
+        .unwrap_or((OtaStatus::StorageError, 0))
+}
+
+async fn ota_reply(reply: OtaReply, req: &McuComPackageHead, tx: &mpsc::Sender<Vec<u8>>) {
+    let data = ComPackage::Raw(reply.encode());
+    protocol_package_send(data, McuComMsgType::OtaDataResp, Some(req), tx).await;
+}
+
+async fn ota_report(status: OtaStatus, slot: Option<Slot>, tx: &mpsc::Sender<Vec<u8>>) {
+    let resp = OtaResp {
+        status: if status == OtaStatus::Done { CmdStatus::Ok } else { CmdStatus::Failed },
+        ota_status: status.into(),
+        progress: 100,
+        slot: slot.map_or("", |s| s.name()).to_string(),
+    };
+    let cmd = ComPackage::Cmd(CmdPackage::new(CmdType::OtaResp as u16, &resp.encode()));
+    if let Err(err) = reliable_request(cmd, McuComMsgType::Cmd, tx).await {
+        println!("ota result report failed: {:?}", err);
+    }
+}
+
+pub(crate) async fn ota_process(pack: McuComPackage, tx: &mpsc::Sender<Vec<u8>>) {
+    let Some(raw) = pack.as_raw() else {
+        return;
+    };
+    let req = match OtaRequest::decode(raw) {
+        Ok(req) => req,
+        Err(status) => {
+            let reply = OtaReply {
+                op: raw.first().copied().unwrap_or(0),
+                status,
+                progress: 0,
+                next_offset: 0,
+            };
+            ota_reply(reply, &pack.head, tx).await;
+            return;
+        }
+    };
+    if let OtaRequest::Begin { .. } = req
+        && let Err(status) = ota_slot_paths()
+    {
+        println!("ota refused: slot_a/slot_b not configured");
+        let reply = OtaReply {
+            op: OtaOp::Begin.into(),
+            status,
+            progress: 0,
+            next_offset: 0,
+        };
+        ota_reply(reply, &pack.head, tx).await;
+        return;
+    }
+    if req.op() != OtaOp::End {
+        let reply = match ota_updater().lock() {
+            Ok(mut updater) => updater.handle(req),
+            Err(_) => return,
+        };
+        ota_reply(reply, &pack.head, tx).await;
+        return;
+    }
+
+    let (session, reply) = match ota_updater().lock() {
+        Ok(mut updater) => match updater.take_for_install() {
+            Ok(session) => {
+                let reply = updater.reply(OtaOp::End, OtaStatus::Installing);
+                (Some(session), reply)
+            }
+            Err(status) => (None, updater.reply(OtaOp::End, status)),
+        },
+        Err(_) => return,
+    };
+    ota_reply(reply, &pack.head, tx).await;
+    let Some(session) = session else {
+        return;
+    };
+    let tx = tx.clone();
+    tokio::spawn(async move {
+        let ret = tokio::task::spawn_blocking(move || {
+            let installer = FileSlotInstaller::from_config()?;
+            ota_install(session, &installer)
+        })
+        .await
+        .unwrap_or(Err(OtaStatus::InstallFailed));
+        let (status, slot) = match ret {
+            Ok(slot) => (OtaStatus::Done, Some(slot)),
+            Err(status) => (status, None),
+        };
+        println!("ota finished: {:?} slot {:?}", status, slot);
+        if let Ok(mut updater) = ota_updater().lock() {
+            updater.complete(status);
+        }
+        ota_report(status, slot, &tx).await;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ini-proc-ota-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn image(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 256) as u8).collect()
+    }
+
+    // 模拟 MCU 端发出的 OtaData 报文体
+    fn begin(img: &[u8]) -> Vec<u8> {
+        let mut out = vec![OtaOp::Begin.into()];
+        out.put_u32(img.len() as u32);
+        out.put_u32(crc32_update(0, img));
+        out.extend_from_slice(b"A612LV-1-V1_0_1");
+        out
+    }
+
+    fn chunk(offset: usize, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![OtaOp::Chunk.into()];
+        out.put_u32(offset as u32);
+        out.extend_from_slice(data);
+        out
+    }
+
+    /// 用文件代替 u-boot 环境
+    struct MarkerBoot(PathBuf);
+
+    impl BootControl for MarkerBoot {
+        fn active_slot(&self) -> Result<Slot> {
+            let name = fs::read_to_string(&self.0).unwrap_or("a".into());
+            Slot::from_name(name.trim()).context("bad marker")
+        }
+        fn set_boot_slot(&self, slot: Slot) -> Result<()> {
+            Ok(fs::write(&self.0, slot.name())?)
+        }
+    }
+
+    fn installer(dir: &Path, a: &str, b: &str) -> FileSlotInstaller {
+        FileSlotInstaller::new(dir.join(a), dir.join(b), Box::new(MarkerBoot(dir.join("marker"))))
+    }
+
+    fn send(updater: &mut OtaUpdater, raw: &[u8]) -> OtaReply {
+        updater.handle(OtaRequest::decode(raw).unwrap())
+    }
+
+    #[test]
+    fn crc32_matches_reference() {
+        assert_eq!(crc32_update(0, b"123456789"), 0xCBF4_3926);
+        let whole = crc32_update(0, b"hello world");
+        assert_eq!(crc32_update(crc32_update(0, b"hello "), b"world"), whole);
+    }
+
+    #[test]
+    fn chunked_transfer_installs_to_inactive_slot() {
+        let dir = test_dir("install");
+        let img = image(1000);
+        let mut updater = OtaUpdater::new(Some(dir.join("staging")));
+
+        assert_eq!(send(&mut updater, &begin(&img)).status, OtaStatus::Ok);
+        let mut last = None;
+        for (i, part) in img.chunks(240).enumerate() {
+            last = Some(send(&mut updater, &chunk(i * 240, part)));
+        }
+        let last = last.unwrap();
+        assert_eq!((last.progress, last.next_offset), (100, 1000));
+        // 重复的分块只确认不重写
+        assert_eq!(send(&mut updater, &chunk(0, &img[..240])).status, OtaStatus::Ok);
+
+        let installer = installer(&dir, "slot_a", "slot_b");
+        fs::write(dir.join("slot_b"), []).unwrap();
+        assert_eq!(installer.boot.active_slot().unwrap(), Slot::A);
+        let session = updater.take_for_install().unwrap();
+        assert_eq!(updater.status().0, OtaStatus::Installing);
+        assert_eq!(ota_install(session, &installer), Ok(Slot::B));
+        updater.complete(OtaStatus::Done);
+
+        assert_eq!(fs::read(dir.join("slot_b")).unwrap(), img);
+        assert_eq!(installer.boot.active_slot().unwrap(), Slot::B);
+        assert!(!dir.join("staging").join(OTA_IMAGE_NAME).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn gaps_and_bad_checksums_are_rejected() {
+        let dir = test_dir("reject");
+        let img = image(500);
+        let mut updater = OtaUpdater::new(Some(dir.clone()));
+        assert_eq!(send(&mut updater, &chunk(0, &img)).status, OtaStatus::NotStarted);
+
+        let mut bad = begin(&img);
+        bad[5] ^= 0xFF; // 篡改 crc32
+        send(&mut updater, &bad);
+        let gap = send(&mut updater, &chunk(100, &img[100..200]));
+        assert_eq!((gap.status, gap.next_offset), (OtaStatus::OffsetMismatch, 0));
+        send(&mut updater, &chunk(0, &img[..300]));
+        assert_eq!(updater.take_for_install().err(), Some(OtaStatus::SizeMismatch));
+        send(&mut updater, &chunk(300, &img[300..]));
+
+        let installer = installer(&dir, "a", "b");
+        let session = updater.take_for_install().unwrap();
+        assert_eq!(ota_install(session, &installer), Err(OtaStatus::ChecksumMismatch));
+        assert!(!dir.join("b").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn missing_slot_is_not_created() {
+        let dir = test_dir("missing");
+        let img = image(100);
+        let mut updater = OtaUpdater::new(Some(dir.join("staging")));
+        send(&mut updater, &begin(&img));
+        send(&mut updater, &chunk(0, &img));
+        let installer = installer(&dir, "a", "b");
+        let session = updater.take_for_install().unwrap();
+        assert_eq!(ota_install(session, &installer), Err(OtaStatus::InstallFailed));
+        assert!(!dir.join("b").exists());
+        assert_eq!(installer.boot.active_slot().unwrap(), Slot::A);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn slots_have_no_defaults() {
+        crate::config::ini_parse::ini_test_init();
+        assert_eq!(ota_slot_paths().err(), Some(OtaStatus::NotConfigured));
+    }
+
+    #[test]
+    fn malformed_requests() {
+        assert_eq!(OtaRequest::decode(&[]), Err(OtaStatus::InvalidRequest));
+        assert_eq!(OtaRequest::decode(&[9]), Err(OtaStatus::InvalidRequest));
+        assert_eq!(OtaRequest::decode(&[1, 0, 0]), Err(OtaStatus::InvalidRequest));
+        assert_eq!(OtaRequest::decode(&[3]), Ok(OtaRequest::End));
+    }
+}
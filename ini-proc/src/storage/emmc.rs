@@ -1,28 +1,22 @@
 use crate::config::ini_parse;
 use anyhow::{Context, Result, anyhow};
 use std::{
-    any, fs,
+    fs,
     io::ErrorKind,
     io::{BufRead, BufReader},
     path::{Path, PathBuf},
     process::{Command, Stdio},
     sync::{
         Condvar, Mutex, OnceLock, RwLock,
-        atomic::{AtomicBool, AtomicUsize, Ordering},
+        atomic::{AtomicBool, Ordering},
     },
     thread,
     time::{Duration, SystemTime},
 };
 
-use nix::{
-    libc::free,
-    sys::{
-        statfs,
-        statvfs::{self, statvfs},
-    },
-};
+use nix::sys::statvfs::{self, statvfs};
 
-const VIDEO_DEVICE_MAX_COUNT: usize = 4;
+pub(crate) const VIDEO_DEVICE_MAX_COUNT: usize = 4;
 const CHECK_INTERVAL_NORMAL: u64 = 60;
 const CHECK_INTERVAL_ERROR: u64 = 5;
 const LOW_SPACE_THRESHOLD_KB: u64 = 1024 * 512;
@@ -38,6 +32,20 @@ pub struct EmmcStatus {
     free_size: u64,
     used_size: u64,
 }
+impl EmmcStatus {
+    pub fn mount_status(&self) -> bool {
+        self.mount_status
+    }
+    pub fn is_read_only(&self) -> bool {
+        self.is_read_only
+    }
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+    pub fn free_size(&self) -> u64 {
+        self.free_size
+    }
+}
 #[derive(Debug, Clone)]
 struct EmmcAttributes {
     emmc_devname: String,
@@ -64,18 +72,18 @@ enum EmmcStateType {
     UpdateInfo,
     MountRetry,
 }
-struct CleanConfig {
-    patterns: &'static [&'static str],
+pub(crate) struct CleanConfig {
+    extensions: &'static [&'static str],
 }
 static CLEAN_MODES: [CleanConfig; 3] = [
     CleanConfig {
-        patterns: &["*.h265", "*.h264", "*/*.h265", "*/*.h264"],
+        extensions: &["h265", "h264"],
     },
     CleanConfig {
-        patterns: &["*.jpeg", "*.jpg", "*/*.jpeg", "*/*.jpg"],
+        extensions: &["jpeg", "jpg"],
     },
     CleanConfig {
-        patterns: &["*.h265", "*.h264", "*/*.h265", "*/*.h264"],
+        extensions: &["h265", "h264"],
     },
 ];
 
@@ -135,7 +143,7 @@ fn _none_emmc_get_config() -> Option<EmmcAttributes> {
 
 pub fn emmc_get_events_path() -> Option<String> {
     let emmc = EMMC.get()?.read().ok()?;
-    if emmc.inner.mount_status == false {
+    if !emmc.inner.mount_status {
         Some(emmc.attributes.tmp_events_dir.clone())
     } else {
         Some(format!(
@@ -144,14 +152,28 @@ pub fn emmc_get_events_path() -> Option<String> {
         ))
     }
 }
+pub fn emmc_get_ota_path() -> Option<String> {
+    let emmc = EMMC.get()?.read().ok()?;
+    if !emmc.inner.mount_status {
+        return None;
+    }
+    Some(format!("{}/{}", &emmc.attributes.emmc_mntpoint, "ota"))
+}
+pub fn emmc_get_log_path() -> Option<String> {
+    let emmc = EMMC.get()?.read().ok()?;
+    if !emmc.inner.mount_status {
+        return None;
+    }
+    Some(format!("{}/{}", &emmc.attributes.emmc_mntpoint, "log"))
+}
 pub fn emmc_get_recoder_path(chn: usize) -> Option<String> {
     if chn >= VIDEO_DEVICE_MAX_COUNT {
         return None;
     }
     let emmc = EMMC.get()?.read().ok()?;
 
-    if emmc.inner.mount_status == false {
-        return None;
+    if !emmc.inner.mount_status {
+        None
     } else {
         Some(format!(
             "{}/{}/{}",
@@ -164,8 +186,8 @@ pub fn emmc_get_recoder_path(chn: usize) -> Option<String> {
 
 pub fn emmc_get_recoder_base_path() -> Option<String> {
     let emmc = EMMC.get()?.read().ok()?;
-    if emmc.inner.mount_status == false {
-        return None;
+    if !emmc.inner.mount_status {
+        None
     } else {
         Some(format!(
             "{}/{}",
@@ -201,7 +223,7 @@ pub(crate) fn emmc_trigger_immediate_check() -> Option<i32> {
 pub(crate) fn emmc_interruptible_sleep(seconds: u64) -> Option<i32> {
     let emmc = EMMC_CTRL.get()?;
 
-    if EMMC_THREAD_QUIT.load(Ordering::SeqCst) == true {
+    if EMMC_THREAD_QUIT.load(Ordering::SeqCst) {
         return Some(0);
     }
     let lock = emmc.force_check_lock.lock().ok()?;
@@ -393,7 +415,7 @@ pub(crate) fn emmc_try_mount() -> Result<()> {
 
         if let Some(stderr) = output.stderr.take() {
             let reader = BufReader::new(stderr);
-            for line in reader.lines().flatten() {
+            for line in reader.lines().map_while(Result::ok) {
                 println!("mkfs: {}", line);
             }
         }
@@ -411,7 +433,7 @@ pub(crate) fn emmc_try_mount() -> Result<()> {
             .context("Failed to spawn fsck.ext4")?;
         if let Some(stdout) = output.stdout.take() {
             let reader = BufReader::new(stdout);
-            for line in reader.lines().flatten() {
+            for line in reader.lines().map_while(Result::ok) {
                 println!("fsck: {}", line);
             }
         }
@@ -470,7 +492,7 @@ pub(crate) fn emmc_try_mount() -> Result<()> {
 pub(crate) fn emmc_check_thread() {
     println!("emmc check thread start");
     let mut check_status = EmmcStateType::MountRetry;
-    while EMMC_THREAD_QUIT.load(Ordering::Relaxed) == false {
+    while !EMMC_THREAD_QUIT.load(Ordering::Relaxed) {
         match check_status {
             EmmcStateType::CheckMount => {
                 if let Some(true) = emmc_mounted_status() {
@@ -536,72 +558,138 @@ pub fn emmc_get_remainfile_count() -> Result<u64> {
     emmc_get_remainfile_count_inner(event_path)
 }
 
-pub(crate) fn emmc_safe_execute_rm(base_path: &str, pattern: &str) -> Result<()> {
-    let full_path = format!("{}/{}", base_path, pattern);
-    if full_path.len() > 400 {
-        return Err(anyhow!("Path too long: {}", full_path));
+/// 清理进度，`failed` 为删除失败的文件数
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EmmcClearProgress {
+    pub total: u32,
+    pub removed: u32,
+    pub failed: u32,
+}
+
+impl EmmcClearProgress {
+    pub fn percent(&self) -> u8 {
+        if self.total == 0 {
+            return 100;
+        }
+        ((self.removed + self.failed) as u64 * 100 / self.total as u64) as u8
     }
-    println!("##### fullpath: {}", full_path);
-    let status = Command::new("sh")
-        .arg("-c")
-        .arg(format!("rm -rf {}", full_path))
-        .status()
-        .context("Failed to execute rm command")?;
+}
 
-    if status.success() {
-        Ok(())
-    } else {
-        Err(anyhow!("rm command failed with status: {}", status))
+/// 已有清理在进行，调用方可用 `err.is::<EmmcClearBusy>()` 区分
+#[derive(Debug)]
+pub struct EmmcClearBusy;
+
+impl std::fmt::Display for EmmcClearBusy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("clear already running")
     }
 }
-pub fn emmc_clear(mode: i32) -> Result<()> {
-    if !(0..=3).contains(&mode) {
-        return Err(anyhow!("mode out of range"));
+
+impl std::error::Error for EmmcClearBusy {}
+
+/// 清理期间置位 `remove_status`，结束(含出错)时复位
+struct EmmcRemoveGuard;
+
+impl EmmcRemoveGuard {
+    fn acquire() -> Result<Self> {
+        let mut emmc = EMMC
+            .get()
+            .context("get emmc failed")?
+            .write()
+            .map_err(|e| anyhow!("Failed to acquire EMMC lock: {:?}", e))?;
+        if emmc.remove_status {
+            return Err(EmmcClearBusy.into());
+        }
+        emmc.remove_status = true;
+        Ok(Self)
     }
-    if emmc_get_mount_status() != Some(true) {
-        return Err(anyhow!("emmc not mounted"));
+}
+
+impl Drop for EmmcRemoveGuard {
+    fn drop(&mut self) {
+        if let Some(Ok(mut emmc)) = EMMC.get().map(|e| e.write()) {
+            emmc.remove_status = false;
+        }
     }
-    let mut emmc = EMMC
-        .get()
-        .context("get emmc failed")?
-        .write()
-        .map_err(|e| anyhow!("Failed to acquire EMMC lock: {:?}", e))?;
-    emmc.remove_status = true;
-    if mode == 3 {
-        let mut has_error = false;
-        for i in 0..3 {
-            if let Err(_) = emmc_clear(i) {
-                has_error = true;
+}
+
+/// Files under `base` and its direct subdirectories with one of the
+/// extensions of `config`.
+pub(crate) fn emmc_clear_collect(base: &Path, config: &CleanConfig) -> Vec<PathBuf> {
+    let matches = |path: &Path| {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|ext| config.extensions.contains(&ext))
+    };
+    let mut files = Vec::new();
+    let Ok(entries) = fs::read_dir(base) else {
+        return files;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(t) if t.is_file() && matches(&path) => files.push(path),
+            Ok(t) if t.is_dir() => {
+                let Ok(sub) = fs::read_dir(&path) else {
+                    continue;
+                };
+                files.extend(
+                    sub.flatten()
+                        .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+                        .map(|e| e.path())
+                        .filter(|p| matches(p)),
+                );
             }
+            _ => {}
         }
-        emmc.remove_status = false;
-        return if has_error {
-            Err(anyhow!("Some clear operations failed"))
-        } else {
-            Ok(())
-        };
     }
-    let target_path = if mode == 0 {
-        emmc_get_recoder_base_path().context("get recoder path failed")?
-    } else {
-        emmc_get_events_path().context("get events path failed")?
-    };
-    let config = &CLEAN_MODES[mode as usize];
+    files
+}
 
-    let mut has_error = false;
-    for pattern in config.patterns {
-        if let Err(e) = emmc_safe_execute_rm(&target_path, pattern) {
-            eprintln!("clear mode {} failed: {}", mode, e);
-            has_error = true;
+pub(crate) fn emmc_clear_files(files: &[PathBuf], mut progress: impl FnMut(&EmmcClearProgress)) -> EmmcClearProgress {
+    let mut state = EmmcClearProgress {
+        total: files.len() as u32,
+        ..Default::default()
+    };
+    for file in files {
+        match fs::remove_file(file) {
+            Ok(()) => state.removed += 1,
+            // 已被别处删掉的不算失败
+            Err(err) if err.kind() == ErrorKind::NotFound => state.removed += 1,
+            Err(err) => {
+                eprintln!("remove {:?} failed: {}", file, err);
+                state.failed += 1;
+            }
         }
+        progress(&state);
     }
-    Command::new("sync").output().context("sync failed")?;
-    emmc.remove_status = false;
-    if has_error {
-        Err(anyhow!("Clear operation completed with errors"))
-    } else {
-        Ok(())
+    state
+}
+
+/// Deletes files by `mode`: 0 recordings, 1 photos, 2 event videos,
+/// 3 all of them. `progress` is called after every file. Fails without
+/// touching anything when the emmc is not mounted or a clear is already
+/// running; a finished clear reports partial failure in `failed`.
+pub fn emmc_clear(mode: i32, progress: impl FnMut(&EmmcClearProgress)) -> Result<EmmcClearProgress> {
+    if !(0..=3).contains(&mode) {
+        return Err(anyhow!("mode out of range"));
+    }
+    if emmc_get_mount_status() != Some(true) {
+        return Err(anyhow!("emmc not mounted"));
     }
+    let record_path = emmc_get_recoder_base_path().context("get recoder path failed")?;
+    let events_path = emmc_get_events_path().context("get events path failed")?;
+    let _guard = EmmcRemoveGuard::acquire()?;
+
+    let modes = if mode == 3 { 0..3 } else { mode..mode + 1 };
+    let mut files = Vec::new();
+    for mode in modes {
+        let target_path = if mode == 0 { &record_path } else { &events_path };
+        files.extend(emmc_clear_collect(Path::new(target_path), &CLEAN_MODES[mode as usize]));
+    }
+    let state = emmc_clear_files(&files, progress);
+    Command::new("sync").output().context("sync failed")?;
+    Ok(state)
 }
 pub fn emmc_get_remove_status() -> Result<bool> {
     Ok(EMMC
@@ -615,8 +703,7 @@ pub fn emmc_check_start() -> thread::JoinHandle<()> {
     EMMC_THREAD_QUIT.store(false, Ordering::Relaxed);
     // emmc_update_info();
     emmc_create_tmp_events_dirs();
-    let join_handle = thread::spawn(emmc_check_thread);
-    return join_handle;
+    thread::spawn(emmc_check_thread)
 }
 
 pub fn emmc_check_stop(handle: thread::JoinHandle<()>) {
@@ -631,8 +718,28 @@ mod tests {
     #[test]
     fn it_works() {
         let path = Path::new("/home/linux/test/testdir/errorfilepath");
-        if let Err(err) = emmc_delete_oldest_file(&path) {
+        if let Err(err) = emmc_delete_oldest_file(path) {
             println!("{:?}", err);
         };
     }
+
+    #[test]
+    fn clear_collects_by_extension_one_level_deep() {
+        let base = std::env::temp_dir().join(format!("ini-proc-clear-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("video_device0/deeper")).unwrap();
+        for f in ["a.h264", "b.jpg", "video_device0/c.h265", "video_device0/deeper/d.h264"] {
+            fs::write(base.join(f), b"x").unwrap();
+        }
+        let mut files = emmc_clear_collect(&base, &CLEAN_MODES[0]);
+        files.sort();
+        assert_eq!(files, [base.join("a.h264"), base.join("video_device0/c.h265")]);
+
+        let mut seen = Vec::new();
+        let state = emmc_clear_files(&files, |p| seen.push(p.percent()));
+        assert_eq!(seen, [50, 100]);
+        assert_eq!((state.total, state.removed, state.failed), (2, 2, 0));
+        assert!(base.join("b.jpg").exists());
+        fs::remove_dir_all(&base).unwrap();
+    }
 }
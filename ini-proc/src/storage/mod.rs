@@ -1,10 +1 @@
 pub mod emmc;
-pub use emmc::{
-    EmmcStatus,
-    emmc_init,
-    emmc_get_events_path,
-    emmc_get_recoder_path,
-    emmc_get_mount_status,
-    emmc_get_info,
-    };
-pub use emmc::*;
\ No newline at end of file
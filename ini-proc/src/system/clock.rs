@@ -0,0 +1,57 @@
+//! 系统时钟
+//!
+//! `ClockBackend` abstracts setting the wall clock, the hardware RTC and
+//! the configured timezone, so time handling can be tested without root.
+
+use crate::config::ini_parse;
+use anyhow::{Context, Result, anyhow, bail};
+use std::{process::Command, time::SystemTime};
+
+pub trait ClockBackend: Send + Sync {
+    /// Current wall clock in epoch seconds.
+    fn now(&self) -> u32;
+    fn set_time(&self, time_s: u32) -> Result<()>;
+    /// Copies the system time into the hardware RTC.
+    fn write_rtc(&self) -> Result<()>;
+    /// Hour offset from UTC, persisted across reboots.
+    fn set_time_zone(&self, time_zone: i8) -> Result<()>;
+}
+
+pub struct SystemClock;
+
+impl ClockBackend for SystemClock {
+    fn now(&self) -> u32 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs().min(u32::MAX as u64) as u32)
+    }
+
+    fn set_time(&self, time_s: u32) -> Result<()> {
+        let ts = libc::timespec {
+            tv_sec: time_s as libc::time_t,
+            tv_nsec: 0,
+        };
+        let ret = unsafe { libc::clock_settime(libc::CLOCK_REALTIME, &ts) };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error()).context("clock_settime failed");
+        }
+        Ok(())
+    }
+
+    fn write_rtc(&self) -> Result<()> {
+        let status = Command::new("hwclock")
+            .args(["--systohc", "--utc"])
+            .status()
+            .context("run hwclock failed")?;
+        if !status.success() {
+            bail!("hwclock exit: {}", status);
+        }
+        Ok(())
+    }
+
+    fn set_time_zone(&self, time_zone: i8) -> Result<()> {
+        ini_parse::ini_put_ini_config("system", "time_zone", &time_zone.to_string())
+            .map_err(|e| anyhow!("set time_zone failed: {:?}", e))?;
+        ini_parse::ini_save_config().map_err(|e| anyhow!("save config failed: {:?}", e))
+    }
+}
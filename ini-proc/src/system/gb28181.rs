@@ -0,0 +1,114 @@
+//! GB28181 配置
+//!
+//! Parameters live in the INI `gb28181` section and every write, from
+//! Config28181 or a Manage Set, goes through `gb28181_configure`. A
+//! change is validated as a whole, saved, and then bumps a generation
+//! counter; `gb28181_watch_start` follows it and sends SIGHUP to the
+//! GB28181 client, which re-reads the INI and re-registers.
+
+use crate::config::ini_parse;
+use crate::config::validate::ConfigError;
+use anyhow::{Context, Result, bail};
+use std::{fs, sync::OnceLock};
+use tokio::sync::watch;
+
+pub const GB28181_SECTION: &str = "gb28181";
+const GB28181_PIDFILE: &str = "/var/run/gb28181.pid";
+static GB28181_GENERATION: OnceLock<watch::Sender<u64>> = OnceLock::new();
+
+fn gb28181_generation() -> &'static watch::Sender<u64> {
+    GB28181_GENERATION.get_or_init(|| watch::channel(0).0)
+}
+
+fn gb28181_subscribe() -> watch::Receiver<u64> {
+    gb28181_generation().subscribe()
+}
+
+/// 通知 GB28181 客户端重新读取配置并注册
+fn gb28181_notify_client() -> Result<()> {
+    let pid: libc::pid_t = fs::read_to_string(GB28181_PIDFILE)
+        .with_context(|| format!("read {} failed", GB28181_PIDFILE))?
+        .trim()
+        .parse()
+        .context("bad pid")?;
+    if unsafe { libc::kill(pid, libc::SIGHUP) } != 0 {
+        bail!("kill {} failed: {}", pid, std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+async fn gb28181_watch(mut rx: watch::Receiver<u64>, reload: impl Fn()) {
+    while rx.changed().await.is_ok() {
+        let generation = *rx.borrow_and_update();
+        println!("gb28181 config changed, generation {}", generation);
+        reload();
+    }
+}
+
+pub fn gb28181_watch_start() -> Option<i32> {
+    let rx = gb28181_subscribe();
+    tokio::spawn(gb28181_watch(rx, || {
+        if let Err(err) = gb28181_notify_client() {
+            println!("gb28181 client not notified: {:#}", err);
+        }
+    }));
+    Some(0)
+}
+
+pub fn gb28181_config() -> Vec<(String, String)> {
+    ini_parse::ini_get_section_items(GB28181_SECTION).unwrap_or_default()
+}
+
+/// Applies a full or partial parameter set and returns the configuration
+/// now in effect. Nothing changes unless every value is valid and the
+/// INI could be saved.
+pub fn gb28181_configure(items: &[(String, String)]) -> Result<Vec<(String, String)>, ConfigError> {
+    if items.is_empty() {
+        return Ok(gb28181_config());
+    }
+    let previous = gb28181_config();
+    ini_parse::ini_set_ini_configs(GB28181_SECTION, items)?;
+    if let Err(err) = ini_parse::ini_save_config() {
+        // 保存失败则恢复内存中的旧值，避免与文件不一致
+        let _ = ini_parse::ini_set_ini_configs(GB28181_SECTION, &previous);
+        return Err(err);
+    }
+    let config = gb28181_config();
+    if config != previous {
+        gb28181_generation().send_modify(|generation| *generation += 1);
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ini_parse::ini_test_init;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    #[tokio::test]
+    async fn change_reaches_watcher() {
+        ini_test_init();
+        let reloads = Arc::new(AtomicUsize::new(0));
+        let counter = reloads.clone();
+        tokio::spawn(gb28181_watch(gb28181_subscribe(), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+        let port = if gb28181_config().iter().any(|(k, v)| k == "devicePort" && v == "5070") {
+            "5080"
+        } else {
+            "5070"
+        };
+        gb28181_configure(&[("devicePort".to_string(), port.to_string())]).unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            while reloads.load(Ordering::SeqCst) == 0 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("watcher not notified");
+    }
+}
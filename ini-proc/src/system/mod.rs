@@ -0,0 +1,5 @@
+pub mod clock;
+pub mod gb28181;
+pub mod network;
+pub mod osd;
+pub mod power;
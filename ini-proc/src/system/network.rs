@@ -0,0 +1,126 @@
+//! 网络配置
+//!
+//! `NetworkBackend` applies an address configuration and checks whether
+//! the link works; `CommandNetwork` does it with `ip`/`udhcpc`/`ping`.
+//! The persisted configuration lives in the INI `network` section.
+
+use crate::command::payload::LinkType;
+use crate::config::ini_parse;
+use crate::config::validate::ConfigError;
+use anyhow::{Context, Result, bail};
+use std::{net::Ipv4Addr, process::Command};
+
+const ETH_IFNAME: &str = "eth0";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetConfig {
+    pub link_type: LinkType,
+    pub dhcp: bool,
+    pub ip: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+}
+
+pub trait NetworkBackend: Send + Sync {
+    fn apply(&self, cfg: &NetConfig) -> Result<()>;
+    /// One connectivity probe; called repeatedly until it passes or the
+    /// confirmation timeout expires.
+    fn check(&self, cfg: &NetConfig) -> bool;
+}
+
+fn run(cmd: &str, args: &[&str]) -> Result<()> {
+    let status = Command::new(cmd)
+        .args(args)
+        .status()
+        .with_context(|| format!("run {} failed", cmd))?;
+    if !status.success() {
+        bail!("{} {:?} exit: {}", cmd, args, status);
+    }
+    Ok(())
+}
+
+pub struct CommandNetwork;
+
+impl NetworkBackend for CommandNetwork {
+    fn apply(&self, cfg: &NetConfig) -> Result<()> {
+        match (cfg.link_type, cfg.dhcp) {
+            (LinkType::Cellular, _) => {
+                let ifname = ini_parse::ini_get_ini_config("quectel", "ifname").unwrap_or("ppp0".into());
+                run("ip", &["route", "replace", "default", "dev", &ifname])
+            }
+            (LinkType::Eth, true) => {
+                run("ip", &["link", "set", ETH_IFNAME, "up"])?;
+                run("udhcpc", &["-i", ETH_IFNAME, "-n", "-q", "-t", "5"])
+            }
+            (LinkType::Eth, false) => {
+                let prefix = u32::from(cfg.netmask).leading_ones();
+                let addr = format!("{}/{}", cfg.ip, prefix);
+                run("ip", &["addr", "flush", "dev", ETH_IFNAME])?;
+                run("ip", &["addr", "add", &addr, "dev", ETH_IFNAME])?;
+                run("ip", &["link", "set", ETH_IFNAME, "up"])?;
+                run("ip", &["route", "replace", "default", "via", &cfg.gateway.to_string(), "dev", ETH_IFNAME])
+            }
+        }
+    }
+
+    fn check(&self, cfg: &NetConfig) -> bool {
+        if cfg.link_type == LinkType::Eth && !cfg.dhcp {
+            return run("ping", &["-c", "1", "-W", "1", &cfg.gateway.to_string()]).is_ok();
+        }
+        // DHCP 和 4G 只要求拿到默认路由
+        Command::new("ip")
+            .args(["route", "show", "default"])
+            .output()
+            .is_ok_and(|o| o.status.success() && !o.stdout.is_empty())
+    }
+}
+
+fn ini_ipv4(key: &str) -> Option<Ipv4Addr> {
+    ini_parse::ini_get_stored_config("network", key)?.parse().ok()
+}
+
+/// The configuration stored in the INI, or None when it is incomplete.
+/// Defaults filled in memory don't count: they say nothing about the
+/// address this device actually uses.
+pub fn network_config_load() -> Option<NetConfig> {
+    let link_type = match ini_parse::ini_get_stored_config("network", "link_type")?.as_str() {
+        "4G" => LinkType::Cellular,
+        "ETH" => LinkType::Eth,
+        _ => return None,
+    };
+    let dhcp = match ini_parse::ini_get_stored_config("network", "link_mode")?.as_str() {
+        "dhcp" => true,
+        "static" => false,
+        _ => return None,
+    };
+    // 只有以太网静态地址需要完整的地址配置
+    let (ip, netmask, gateway) = if link_type == LinkType::Eth && !dhcp {
+        (ini_ipv4("local_ip")?, ini_ipv4("local_netmask")?, ini_ipv4("local_gateway")?)
+    } else {
+        let ip = |key| ini_ipv4(key).unwrap_or(Ipv4Addr::UNSPECIFIED);
+        (ip("local_ip"), ip("local_netmask"), ip("local_gateway"))
+    };
+    Some(NetConfig {
+        link_type,
+        dhcp,
+        ip,
+        netmask,
+        gateway,
+    })
+}
+
+/// DHCP/4G 时保留原有的静态地址
+pub fn network_config_save(cfg: &NetConfig) -> Result<(), ConfigError> {
+    let link_type = match cfg.link_type {
+        LinkType::Eth => "ETH",
+        LinkType::Cellular => "4G",
+    };
+    ini_parse::ini_put_ini_config("network", "link_type", link_type)?;
+    ini_parse::ini_put_ini_config("network", "link_mode", if cfg.dhcp { "dhcp" } else { "static" })?;
+    if !cfg.dhcp {
+        ini_parse::ini_put_ini_config("network", "local_ip", &cfg.ip.to_string())?;
+        ini_parse::ini_put_ini_config("network", "local_netmask", &cfg.netmask.to_string())?;
+        ini_parse::ini_put_ini_config("network", "local_gateway", &cfg.gateway.to_string())?;
+    }
+    ini_parse::ini_save_config()
+}
@@ -0,0 +1,70 @@
+//! OSD 设置
+//!
+//! The OSD corner (`system/coordinate`) places the time overlay that the
+//! capture and clip backends burn into their output. It is read when a
+//! snapshot or clip starts, so a change applies from the next one on.
+
+use crate::communication::types::Direction;
+use crate::config::ini_parse;
+use crate::config::validate::ConfigError;
+
+const DEFAULT_COORDINATE: Direction = Direction::TopLeft;
+const OSD_MARGIN: u32 = 10;
+
+/// `EXTRA` 是占位值，不是有效位置
+pub fn osd_coordinate_parse(value: &str) -> Option<Direction> {
+    let direction = Direction::try_from(value.parse::<u8>().ok()?).ok()?;
+    (direction != Direction::EXTRA).then_some(direction)
+}
+
+/// INI 为准，管理通道直接改键值时也能读到
+pub fn osd_coordinate_get() -> Direction {
+    ini_parse::ini_get_ini_config("system", "coordinate")
+        .and_then(|v| osd_coordinate_parse(&v))
+        .unwrap_or(DEFAULT_COORDINATE)
+}
+
+pub fn osd_coordinate_set(direction: Direction) -> Result<(), ConfigError> {
+    let value = u8::from(direction).to_string();
+    ini_parse::ini_put_ini_config("system", "coordinate", &value)?;
+    ini_parse::ini_save_config()
+}
+
+/// ffmpeg drawtext filter showing the local time at `direction`. Values
+/// that name no single position fall back to the default corner.
+pub fn osd_drawtext_filter(direction: Direction) -> String {
+    let m = OSD_MARGIN;
+    let (left, center, right) = (format!("{m}"), "(w-tw)/2".to_string(), format!("w-tw-{m}"));
+    let (top, middle, bottom) = (format!("{m}"), "(h-th)/2".to_string(), format!("h-th-{m}"));
+    let (x, y) = match direction {
+        Direction::Top | Direction::TopCenter => (center, top),
+        Direction::TopRight => (right, top),
+        Direction::MiddleLeft => (left, middle),
+        Direction::Center => (center, middle),
+        Direction::MiddleRight => (right, middle),
+        Direction::BottomLeft => (left, bottom),
+        Direction::BottomCenter => (center, bottom),
+        Direction::BottomRight => (right, bottom),
+        _ => (left, top),
+    };
+    format!(
+        "drawtext=text='%{{localtime\\:%Y-%m-%d %H\\:%M\\:%S}}':fontcolor=white:fontsize=24:box=1:boxcolor=black@0.4:x={}:y={}",
+        x, y
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drawtext_follows_corner() {
+        assert!(osd_drawtext_filter(Direction::TopLeft).ends_with(":x=10:y=10"));
+        assert!(osd_drawtext_filter(Direction::BottomRight).ends_with(":x=w-tw-10:y=h-th-10"));
+        assert!(osd_drawtext_filter(Direction::Center).ends_with(":x=(w-tw)/2:y=(h-th)/2"));
+        assert_eq!(
+            osd_drawtext_filter(Direction::CoordinateAll),
+            osd_drawtext_filter(DEFAULT_COORDINATE)
+        );
+    }
+}
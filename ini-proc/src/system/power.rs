@@ -0,0 +1,397 @@
+//! 电源管理
+//!
+//! Deep sleep quiesces `PowerStage`s in order, enters low power through a
+//! `PowerBackend` and restores the stages in reverse order on wake-up.
+//! `RecorderStage` first has the continuous recorder close its segments
+//! in `record/video_deviceN`. Snapshot and clip writers hold a
+//! `PowerHold` while they write; once the state turns `Sleeping` no new
+//! hold is granted, a running clip stops its live part and completes the
+//! file, and the stage waits until the last hold is dropped. So no file
+//! is left half written when the MCU cuts power. `StorageMonitorStage`
+//! stops the eMMC check thread during sleep.
+
+use crate::storage::emmc::{VIDEO_DEVICE_MAX_COUNT, emmc_check_start, emmc_check_stop, emmc_get_recoder_path};
+use anyhow::{Context, Result, anyhow, bail};
+use std::{
+    fs,
+    path::Path,
+    process::Command,
+    sync::{
+        Arc, Condvar, Mutex, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::watch;
+
+/// 等待写入方收尾的上限，超时则放弃休眠
+const RECORDER_STOP_TIMEOUT: Duration = Duration::from_secs(10);
+const RECORDER_PIDFILE: &str = "/var/run/recorder.pid";
+/// 分段这么久没有写入视为已关闭
+const SEGMENT_IDLE: Duration = Duration::from_secs(1);
+const SEGMENT_POLL: Duration = Duration::from_millis(100);
+static MEDIA_WRITERS: OnceLock<MediaWriters> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PowerState {
+    Running,
+    Sleeping,
+}
+
+pub trait PowerBackend: Send + Sync {
+    /// Enters low power and returns after wake-up. `seconds` 0 sleeps
+    /// until the MCU wakes us.
+    fn suspend(&self, seconds: u32) -> Result<()>;
+}
+
+/// One subsystem taking part in deep sleep.
+pub trait PowerStage: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn quiesce(&self) -> Result<()>;
+    fn restore(&self) -> Result<()>;
+}
+
+/// The power state together with the number of media writers running.
+pub struct MediaWriters {
+    count: Mutex<usize>,
+    done: Condvar,
+    state: watch::Sender<PowerState>,
+}
+
+impl MediaWriters {
+    pub(crate) fn new() -> Self {
+        Self {
+            count: Mutex::new(0),
+            done: Condvar::new(),
+            state: watch::channel(PowerState::Running).0,
+        }
+    }
+
+    /// 休眠中返回 None
+    fn hold(&'static self) -> Option<PowerHold> {
+        let mut count = self.count.lock().ok()?;
+        let state = self.state.subscribe();
+        if *state.borrow() == PowerState::Sleeping {
+            return None;
+        }
+        *count += 1;
+        Some(PowerHold { writers: self, state })
+    }
+
+    /// 置为 Sleeping 并等待所有写入方释放，超时则恢复 Running
+    fn quiesce(&self, timeout: Duration) -> Result<()> {
+        let count = self.count.lock().map_err(|_| anyhow!("writer count poisoned"))?;
+        self.state.send_replace(PowerState::Sleeping);
+        let (count, wait) = self
+            .done
+            .wait_timeout_while(count, timeout, |count| *count > 0)
+            .map_err(|_| anyhow!("writer count poisoned"))?;
+        if wait.timed_out() {
+            let busy = *count;
+            drop(count);
+            self.state.send_replace(PowerState::Running);
+            bail!("{} media writers still busy", busy);
+        }
+        Ok(())
+    }
+
+    fn resume(&self) {
+        self.state.send_replace(PowerState::Running);
+    }
+}
+
+/// Held by a snapshot or clip writer while it writes.
+pub struct PowerHold {
+    writers: &'static MediaWriters,
+    state: watch::Receiver<PowerState>,
+}
+
+impl PowerHold {
+    /// 休眠请求到来后为 true，写入方应尽快收尾
+    pub fn sleep_requested(&self) -> bool {
+        *self.state.borrow() == PowerState::Sleeping
+    }
+}
+
+impl Drop for PowerHold {
+    fn drop(&mut self) {
+        if let Ok(mut count) = self.writers.count.lock() {
+            *count -= 1;
+        }
+        self.writers.done.notify_all();
+    }
+}
+
+fn media_writers() -> &'static MediaWriters {
+    MEDIA_WRITERS.get_or_init(MediaWriters::new)
+}
+
+/// Registers a media writer; None while the device goes to sleep.
+pub fn power_hold() -> Option<PowerHold> {
+    media_writers().hold()
+}
+
+/// 定时唤醒用 rtcwake，否则直接写 /sys/power/state
+pub struct SysfsPower;
+
+impl PowerBackend for SysfsPower {
+    fn suspend(&self, seconds: u32) -> Result<()> {
+        if seconds > 0 {
+            let status = Command::new("rtcwake")
+                .args(["-m", "mem", "-s", &seconds.to_string()])
+                .status()
+                .context("run rtcwake failed")?;
+            if !status.success() {
+                bail!("rtcwake exit: {}", status);
+            }
+            return Ok(());
+        }
+        fs::write("/sys/power/state", "mem").context("write /sys/power/state failed")
+    }
+}
+
+/// The continuous recorder writing `record/video_deviceN`.
+pub trait RecorderControl: Send + Sync {
+    /// Closes the current segments and stops writing; returns once no
+    /// segment is written any more, or fails after `timeout`.
+    fn pause(&self, timeout: Duration) -> Result<()>;
+    fn resume(&self) -> Result<()>;
+}
+
+/// 录像进程: SIGUSR1 关闭当前分段并暂停，SIGUSR2 继续
+pub struct SignalRecorder;
+
+impl SignalRecorder {
+    /// 没有 pid 文件说明录像没在运行，返回 false
+    fn signal(sig: libc::c_int) -> Result<bool> {
+        let Ok(pid) = fs::read_to_string(RECORDER_PIDFILE) else {
+            return Ok(false);
+        };
+        let pid: libc::pid_t = pid.trim().parse().context("bad pid")?;
+        if unsafe { libc::kill(pid, sig) } != 0 {
+            bail!("kill {} failed: {}", pid, std::io::Error::last_os_error());
+        }
+        Ok(true)
+    }
+}
+
+impl RecorderControl for SignalRecorder {
+    fn pause(&self, timeout: Duration) -> Result<()> {
+        if !Self::signal(libc::SIGUSR1)? {
+            return Ok(());
+        }
+        let dirs: Vec<String> = (0..VIDEO_DEVICE_MAX_COUNT).filter_map(emmc_get_recoder_path).collect();
+        let deadline = Instant::now() + timeout;
+        while !power_segments_idle(&dirs, SEGMENT_IDLE, SystemTime::now()) {
+            if Instant::now() > deadline {
+                bail!("recorder still writing");
+            }
+            thread::sleep(SEGMENT_POLL);
+        }
+        Ok(())
+    }
+    fn resume(&self) -> Result<()> {
+        Self::signal(libc::SIGUSR2).map(|_| ())
+    }
+}
+
+/// 所有目录里最后一次写入都在 `idle` 之前
+pub(crate) fn power_segments_idle(dirs: &[String], idle: Duration, now: SystemTime) -> bool {
+    dirs.iter()
+        .filter_map(|dir| fs::read_dir(Path::new(dir)).ok())
+        .flat_map(|entries| entries.flatten())
+        .filter_map(|e| e.metadata().ok()?.modified().ok())
+        .all(|modified| now.duration_since(modified).is_ok_and(|age| age >= idle))
+}
+
+/// 停止媒体写入: 连续录像关闭分段，抓拍/事件录像收尾，并等它们确认结束
+pub struct RecorderStage {
+    writers: &'static MediaWriters,
+    recorder: Arc<dyn RecorderControl>,
+    timeout: Duration,
+}
+
+impl RecorderStage {
+    pub fn new(writers: &'static MediaWriters, recorder: Arc<dyn RecorderControl>, timeout: Duration) -> Self {
+        Self {
+            writers,
+            recorder,
+            timeout,
+        }
+    }
+}
+
+impl Default for RecorderStage {
+    fn default() -> Self {
+        Self::new(media_writers(), Arc::new(SignalRecorder), RECORDER_STOP_TIMEOUT)
+    }
+}
+
+impl PowerStage for RecorderStage {
+    fn name(&self) -> &'static str {
+        "recorder"
+    }
+    fn quiesce(&self) -> Result<()> {
+        self.recorder.pause(self.timeout).context("pause recorder failed")?;
+        if let Err(err) = self.writers.quiesce(self.timeout) {
+            if let Err(err) = self.recorder.resume() {
+                println!("resume recorder failed: {:?}", err);
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+    fn restore(&self) -> Result<()> {
+        self.writers.resume();
+        self.recorder.resume()
+    }
+}
+
+/// 文件落盘
+pub struct FlushStage;
+
+impl PowerStage for FlushStage {
+    fn name(&self) -> &'static str {
+        "flush"
+    }
+    fn quiesce(&self) -> Result<()> {
+        unsafe { libc::sync() };
+        Ok(())
+    }
+    fn restore(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub type MonitorStart = Box<dyn Fn() -> thread::JoinHandle<()> + Send + Sync>;
+pub type MonitorStop = Box<dyn Fn(thread::JoinHandle<()>) + Send + Sync>;
+
+/// eMMC 检测线程: 休眠前停止，唤醒后重新启动
+pub struct StorageMonitorStage {
+    handle: Mutex<Option<thread::JoinHandle<()>>>,
+    stopped: AtomicBool,
+    start: MonitorStart,
+    stop: MonitorStop,
+}
+
+impl StorageMonitorStage {
+    /// Takes over the running check thread started with `emmc_check_start`.
+    pub fn new(handle: thread::JoinHandle<()>) -> Self {
+        Self::with(handle, Box::new(emmc_check_start), Box::new(emmc_check_stop))
+    }
+
+    pub fn with(handle: thread::JoinHandle<()>, start: MonitorStart, stop: MonitorStop) -> Self {
+        Self {
+            handle: Mutex::new(Some(handle)),
+            stopped: AtomicBool::new(false),
+            start,
+            stop,
+        }
+    }
+}
+
+impl PowerStage for StorageMonitorStage {
+    fn name(&self) -> &'static str {
+        "storage monitor"
+    }
+    fn quiesce(&self) -> Result<()> {
+        let handle = self.handle.lock().map_err(|_| anyhow!("monitor handle poisoned"))?.take();
+        if let Some(handle) = handle {
+            (self.stop)(handle);
+            self.stopped.store(true, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+    fn restore(&self) -> Result<()> {
+        // 只重启休眠前在运行的
+        if self.stopped.swap(false, Ordering::SeqCst) {
+            let handle = (self.start)();
+            *self.handle.lock().map_err(|_| anyhow!("monitor handle poisoned"))? = Some(handle);
+        }
+        Ok(())
+    }
+}
+
+/// Quiesces `stages` in order. On failure the stages already quiesced
+/// are restored and the error is returned.
+pub fn power_quiesce(stages: &[Arc<dyn PowerStage>]) -> Result<()> {
+    for (i, stage) in stages.iter().enumerate() {
+        if let Err(err) = stage.quiesce() {
+            power_restore(&stages[..i]);
+            return Err(err.context(format!("quiesce {} failed", stage.name())));
+        }
+        println!("power: {} quiesced", stage.name());
+    }
+    Ok(())
+}
+
+/// Restores `stages` in reverse order; failures are logged, the rest
+/// still restored.
+pub fn power_restore(stages: &[Arc<dyn PowerStage>]) {
+    for stage in stages.iter().rev() {
+        match stage.restore() {
+            Ok(()) => println!("power: {} restored", stage.name()),
+            Err(err) => println!("power: restore {} failed: {:?}", stage.name(), err),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// 连续录像总是立即暂停
+    pub(crate) struct IdleRecorder;
+
+    impl RecorderControl for IdleRecorder {
+        fn pause(&self, _timeout: Duration) -> Result<()> {
+            Ok(())
+        }
+        fn resume(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn stage(timeout: Duration) -> RecorderStage {
+        RecorderStage::new(Box::leak(Box::new(MediaWriters::new())), Arc::new(IdleRecorder), timeout)
+    }
+
+    #[test]
+    fn quiesce_waits_for_writers() {
+        let stage = stage(Duration::from_secs(5));
+        let hold = stage.writers.hold().unwrap();
+        let writer = thread::spawn(move || {
+            // 写入方看到休眠请求后收尾
+            while !hold.sleep_requested() {
+                thread::sleep(Duration::from_millis(5));
+            }
+        });
+        stage.quiesce().unwrap();
+        writer.join().unwrap();
+        assert!(stage.writers.hold().is_none());
+        stage.restore().unwrap();
+        assert!(stage.writers.hold().is_some());
+    }
+
+    #[test]
+    fn busy_writer_aborts_quiesce() {
+        let stage = stage(Duration::from_millis(20));
+        let _hold = stage.writers.hold().unwrap();
+        assert!(stage.quiesce().is_err());
+        assert!(stage.writers.hold().is_some());
+    }
+
+    #[test]
+    fn segments_idle_after_last_write() {
+        let dir = std::env::temp_dir().join(format!("ini-proc-segments-idle-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.h264"), b"x").unwrap();
+        let dirs = [dir.to_str().unwrap().to_string(), "/nonexistent".to_string()];
+        let now = SystemTime::now();
+        assert!(!power_segments_idle(&dirs, SEGMENT_IDLE, now));
+        assert!(power_segments_idle(&dirs, SEGMENT_IDLE, now + Duration::from_secs(2)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}